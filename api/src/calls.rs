@@ -1,4 +1,14 @@
+use crate::error::{ErrorKind, ShfsError};
+use crate::filesystem_entry::FsTimespec;
+use crate::lock::FileLock;
+use crate::protocol::{
+    Capabilities, CALL_LOCKS, CALL_READDIRPLUS, CALL_XATTR, MAX_IO_SIZE, MAX_NAME_LEN, MAX_PATH_LEN,
+    MAX_XATTR_NAME_LEN, MAX_XATTR_SIZE,
+};
 use serde::{Deserialize, Serialize};
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 /// Additional Information about a request
@@ -12,9 +22,20 @@ pub struct RequestInfo {
 pub enum Call {
     // Filesystem Requests
     #[serde(rename = "read_dir")]
-    ReadDir { info: RequestInfo, path: String },
+    ReadDir {
+        info: RequestInfo,
+        #[serde(with = "crate::os_bytes")]
+        path: PathBuf,
+    },
+    /// Lists a directory together with the attributes of its entries
+    #[serde(rename = "read_dir_plus")]
+    ReadDirPlus { info: RequestInfo, ino: u64 },
     #[serde(rename = "get_entry")]
-    GetEntry { info: RequestInfo, path: String },
+    GetEntry {
+        info: RequestInfo,
+        #[serde(with = "crate::os_bytes")]
+        path: PathBuf,
+    },
     #[serde(rename = "get_entry_from_inode")]
     GetEntryFromInode { info: RequestInfo, ino: u64 },
     #[serde(rename = "get_path_from_inode")]
@@ -23,6 +44,8 @@ pub enum Call {
     Read {
         info: RequestInfo,
         ino: u64,
+        /// Handle returned by [Call::Open], the file is opened by `ino` if unset
+        fh: Option<u64>,
         offset: i64,
         size: u32,
     },
@@ -30,42 +53,160 @@ pub enum Call {
     Rename {
         info: RequestInfo,
         parent: u64,
-        name: String,
+        #[serde(with = "crate::os_bytes")]
+        name: OsString,
         nparent: u64,
-        nname: String,
+        #[serde(with = "crate::os_bytes")]
+        nname: OsString,
     },
     #[serde(rename = "mkdir")]
     Mkdir {
         info: RequestInfo,
         parent: u64,
-        name: String,
+        #[serde(with = "crate::os_bytes")]
+        name: OsString,
+        /// Permission bits of the new directory
+        mode: u32,
     },
     #[serde(rename = "rmdir")]
     Rmdir {
         info: RequestInfo,
         parent: u64,
-        name: String,
+        #[serde(with = "crate::os_bytes")]
+        name: OsString,
     },
     #[serde(rename = "create")]
     Create {
         info: RequestInfo,
         parent: u64,
-        name: String,
+        #[serde(with = "crate::os_bytes")]
+        name: OsString,
+        flags: i32,
+        /// Permission bits of the new file
+        mode: u32,
     },
     #[serde(rename = "unlink")]
     Unlink {
         info: RequestInfo,
         parent: u64,
-        name: String,
+        #[serde(with = "crate::os_bytes")]
+        name: OsString,
     },
     #[serde(rename = "write")]
     Write {
         info: RequestInfo,
         ino: u64,
+        /// Handle returned by [Call::Open], the file is opened by `ino` if unset
+        fh: Option<u64>,
         offset: i64,
+        #[serde(with = "serde_bytes")]
         data: Vec<u8>,
     },
 
+    #[serde(rename = "set_attr")]
+    SetAttr {
+        info: RequestInfo,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<FsTimespec>,
+        mtime: Option<FsTimespec>,
+    },
+    #[serde(rename = "symlink")]
+    Symlink {
+        info: RequestInfo,
+        parent: u64,
+        #[serde(with = "crate::os_bytes")]
+        name: OsString,
+        #[serde(with = "crate::os_bytes")]
+        target: PathBuf,
+    },
+    #[serde(rename = "read_link")]
+    ReadLink { info: RequestInfo, ino: u64 },
+    #[serde(rename = "open")]
+    Open {
+        info: RequestInfo,
+        ino: u64,
+        flags: i32,
+    },
+    #[serde(rename = "release")]
+    Release { info: RequestInfo, fh: u64 },
+    #[serde(rename = "fsync")]
+    Fsync {
+        info: RequestInfo,
+        ino: u64,
+        /// Handle returned by [Call::Open], the file is opened by `ino` if unset
+        fh: Option<u64>,
+        datasync: bool,
+    },
+    #[serde(rename = "statfs")]
+    StatFs { info: RequestInfo },
+    #[serde(rename = "get_lock")]
+    GetLock {
+        info: RequestInfo,
+        ino: u64,
+        owner: u64,
+        lock: FileLock,
+    },
+    #[serde(rename = "set_lock")]
+    SetLock {
+        info: RequestInfo,
+        ino: u64,
+        owner: u64,
+        lock: FileLock,
+        sleep: bool,
+    },
+    #[serde(rename = "mknod")]
+    Mknod {
+        info: RequestInfo,
+        parent: u64,
+        #[serde(with = "crate::os_bytes")]
+        name: OsString,
+        mode: u32,
+        rdev: u32,
+    },
+    #[serde(rename = "link")]
+    Link {
+        info: RequestInfo,
+        ino: u64,
+        nparent: u64,
+        #[serde(with = "crate::os_bytes")]
+        nname: OsString,
+    },
+    #[serde(rename = "get_xattr")]
+    GetXattr {
+        info: RequestInfo,
+        ino: u64,
+        #[serde(with = "crate::os_bytes")]
+        name: OsString,
+        size: u32,
+    },
+    #[serde(rename = "list_xattr")]
+    ListXattr {
+        info: RequestInfo,
+        ino: u64,
+        size: u32,
+    },
+    #[serde(rename = "set_xattr")]
+    SetXattr {
+        info: RequestInfo,
+        ino: u64,
+        #[serde(with = "crate::os_bytes")]
+        name: OsString,
+        #[serde(with = "serde_bytes")]
+        value: Vec<u8>,
+        flags: i32,
+    },
+    #[serde(rename = "remove_xattr")]
+    RemoveXattr {
+        info: RequestInfo,
+        ino: u64,
+        #[serde(with = "crate::os_bytes")]
+        name: OsString,
+    },
+
     // Server Requests
     #[serde(rename = "list_volumes")]
     ListVolumes {},
@@ -73,4 +214,195 @@ pub enum Call {
     VolumeLookup { name: String },
     #[serde(rename = "server_info")]
     ServerInfo {},
+    /// Opens the connection, exchanging protocol version and [Capabilities].
+    /// Has to be the first call and is always sent with the default [Encoding](crate::encoding::Encoding).
+    #[serde(rename = "handshake")]
+    Handshake {
+        version: u32,
+        capabilities: Capabilities,
+    },
+}
+
+impl Call {
+    /// Returns the name of the call as sent on the wire
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Call::ReadDir { .. } => "read_dir",
+            Call::ReadDirPlus { .. } => "read_dir_plus",
+            Call::GetEntry { .. } => "get_entry",
+            Call::GetEntryFromInode { .. } => "get_entry_from_inode",
+            Call::GetPathFromInode { .. } => "get_path_from_inode",
+            Call::Read { .. } => "read",
+            Call::Rename { .. } => "rename",
+            Call::Mkdir { .. } => "mkdir",
+            Call::Rmdir { .. } => "rmdir",
+            Call::Create { .. } => "create",
+            Call::Unlink { .. } => "unlink",
+            Call::Write { .. } => "write",
+            Call::SetAttr { .. } => "set_attr",
+            Call::Symlink { .. } => "symlink",
+            Call::ReadLink { .. } => "read_link",
+            Call::Open { .. } => "open",
+            Call::Release { .. } => "release",
+            Call::Fsync { .. } => "fsync",
+            Call::StatFs { .. } => "statfs",
+            Call::GetLock { .. } => "get_lock",
+            Call::SetLock { .. } => "set_lock",
+            Call::Mknod { .. } => "mknod",
+            Call::Link { .. } => "link",
+            Call::GetXattr { .. } => "get_xattr",
+            Call::ListXattr { .. } => "list_xattr",
+            Call::SetXattr { .. } => "set_xattr",
+            Call::RemoveXattr { .. } => "remove_xattr",
+            Call::ListVolumes {} => "list_volumes",
+            Call::VolumeLookup { .. } => "volume_lookup",
+            Call::ServerInfo {} => "server_info",
+            Call::Handshake { .. } => "handshake",
+        };
+    }
+
+    /// Returns the optional [calls](crate::protocol) the call belongs to, `None` if every server supports it
+    pub fn optional(&self) -> Option<&'static str> {
+        return match self {
+            Call::ReadDirPlus { .. } => Some(CALL_READDIRPLUS),
+            Call::GetLock { .. } | Call::SetLock { .. } => Some(CALL_LOCKS),
+            Call::GetXattr { .. }
+            | Call::ListXattr { .. }
+            | Call::SetXattr { .. }
+            | Call::RemoveXattr { .. } => Some(CALL_XATTR),
+            _ => None,
+        };
+    }
+
+    /// Returns the [RequestInfo] of calls on a volume
+    pub fn info(&self) -> Option<&RequestInfo> {
+        return match self {
+            Call::ReadDir { info, .. }
+            | Call::ReadDirPlus { info, .. }
+            | Call::GetEntry { info, .. }
+            | Call::GetEntryFromInode { info, .. }
+            | Call::GetPathFromInode { info, .. }
+            | Call::Read { info, .. }
+            | Call::Rename { info, .. }
+            | Call::Mkdir { info, .. }
+            | Call::Rmdir { info, .. }
+            | Call::Create { info, .. }
+            | Call::Unlink { info, .. }
+            | Call::Write { info, .. }
+            | Call::SetAttr { info, .. }
+            | Call::Symlink { info, .. }
+            | Call::ReadLink { info, .. }
+            | Call::Open { info, .. }
+            | Call::Release { info, .. }
+            | Call::Fsync { info, .. }
+            | Call::StatFs { info }
+            | Call::GetLock { info, .. }
+            | Call::SetLock { info, .. }
+            | Call::Mknod { info, .. }
+            | Call::Link { info, .. }
+            | Call::GetXattr { info, .. }
+            | Call::ListXattr { info, .. }
+            | Call::SetXattr { info, .. }
+            | Call::RemoveXattr { info, .. } => Some(info),
+            Call::ListVolumes {}
+            | Call::VolumeLookup { .. }
+            | Call::ServerInfo {}
+            | Call::Handshake { .. } => None,
+        };
+    }
+
+    /// Checks the arguments of the call independent of the volume.
+    /// Paths, names and sizes exceeding the limits of the [protocol](crate::protocol) are refused,
+    /// as are names which are not a single path component.
+    pub fn validate(&self) -> Result<(), ShfsError> {
+        match self {
+            Call::ReadDir { path, .. } | Call::GetEntry { path, .. } => check_path(path)?,
+            Call::Read { size, .. } if *size > MAX_IO_SIZE => {
+                return Err(ShfsError::new(ErrorKind::InvalidInput, "Read exceeds maximum size"));
+            }
+            Call::Write { data, .. } if data.len() > MAX_IO_SIZE as usize => {
+                return Err(ShfsError::new(ErrorKind::InvalidInput, "Write exceeds maximum size"));
+            }
+            Call::Rename { name, nname, .. } => {
+                check_name(name)?;
+                check_name(nname)?;
+            }
+            Call::Mkdir { name, .. }
+            | Call::Rmdir { name, .. }
+            | Call::Create { name, .. }
+            | Call::Unlink { name, .. }
+            | Call::Mknod { name, .. } => check_name(name)?,
+            Call::Link { nname, .. } => check_name(nname)?,
+            Call::Symlink { name, target, .. } => {
+                check_name(name)?;
+                if target.as_os_str().is_empty() {
+                    return Err(ShfsError::new(ErrorKind::NotFound, "Empty symlink target"));
+                }
+                check_path(target)?;
+            }
+            Call::GetLock { lock, .. } | Call::SetLock { lock, .. } if lock.start > lock.end => {
+                return Err(ShfsError::new(ErrorKind::InvalidInput, "Lock range ends before it starts"));
+            }
+            Call::GetXattr { name, .. } | Call::RemoveXattr { name, .. } => check_xattr_name(name)?,
+            Call::SetXattr { name, value, .. } => {
+                check_xattr_name(name)?;
+                if value.len() > MAX_XATTR_SIZE {
+                    return Err(ShfsError::new(ErrorKind::OutOfRange, "Extended attribute value too large"));
+                }
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+}
+
+/// Checks that `path` fits [MAX_PATH_LEN] and contains no NUL
+fn check_path(path: &Path) -> Result<(), ShfsError> {
+    let path = path.as_os_str().as_bytes();
+    if path.len() > MAX_PATH_LEN {
+        return Err(ShfsError::new(ErrorKind::NameTooLong, "Path too long"));
+    }
+    if path.contains(&0) {
+        return Err(ShfsError::new(ErrorKind::InvalidInput, "Path contains NUL"));
+    }
+    return Ok(());
+}
+
+/// Checks that `name` is a single path component fitting [MAX_NAME_LEN]
+fn check_name(name: &OsStr) -> Result<(), ShfsError> {
+    let name = name.as_bytes();
+    if name.len() > MAX_NAME_LEN {
+        return Err(ShfsError::new(ErrorKind::NameTooLong, "Name too long"));
+    }
+    if name.is_empty() || name == b"." || name == b".." || name.contains(&b'/') || name.contains(&0) {
+        return Err(ShfsError::new(ErrorKind::InvalidInput, "Invalid name"));
+    }
+    return Ok(());
+}
+
+/// Checks the name of an Extended Attribute
+fn check_xattr_name(name: &OsStr) -> Result<(), ShfsError> {
+    let name = name.as_bytes();
+    if name.len() > MAX_XATTR_NAME_LEN {
+        return Err(ShfsError::new(ErrorKind::OutOfRange, "Extended attribute name too long"));
+    }
+    if name.is_empty() || name.contains(&0) {
+        return Err(ShfsError::new(ErrorKind::InvalidInput, "Invalid extended attribute name"));
+    }
+    return Ok(());
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A [Call] tagged with an ID used to match it with its [Reply](crate::responses::Reply)
+pub struct Request {
+    /// ID of the request, unique per connection
+    pub id: u64,
+    /// The actual call
+    pub call: Call,
+}
+
+#[derive(Deserialize, Debug)]
+/// Only the id of a [Request], to answer requests whose [Call] can not be decoded
+pub struct RequestId {
+    pub id: u64,
 }
@@ -7,6 +7,8 @@ pub struct ServerConfig {
     pub name: Option<String>,
     /// List of volumes
     pub volumes: Vec<VolumeConfig>,
+    /// Directory storing the inode tables of the volumes, so inodes stay valid across restarts
+    pub state_dir: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -26,4 +28,14 @@ pub struct VolumeConfig {
     pub trash_enabled: Option<bool>, // TODO : Implement Trash
     /// Read Only Volume
     pub readonly: Option<bool>,
+    /// Allow clients to create device nodes
+    pub allow_devices: Option<bool>,
+    /// Enable Extended Attributes
+    pub xattrs: Option<bool>,
+    /// Namespaces of Extended Attributes accessible by clients, e.g. `user`
+    pub xattr_namespaces: Option<Vec<String>>,
+    /// Size in bytes reported to clients as the capacity of the volume
+    pub quota: Option<u64>,
+    /// Allow access to other filesystems mounted inside the volume
+    pub cross_mounts: Option<bool>,
 }
@@ -0,0 +1,60 @@
+use crate::protocol::MAX_IO_SIZE;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// Wire Encoding of Calls and Responses
+pub enum Encoding {
+    /// Human readable JSON, useful for debugging
+    #[serde(rename = "json")]
+    Json,
+    /// Compact binary CBOR
+    #[serde(rename = "cbor")]
+    Cbor,
+}
+
+impl Default for Encoding {
+    /// Connections start out with JSON until another [Encoding] is negotiated
+    fn default() -> Encoding {
+        return Encoding::Json;
+    }
+}
+
+impl Encoding {
+    /// Parses the name of an [Encoding] as used on the command line
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        return match name {
+            "json" => Some(Encoding::Json),
+            "cbor" => Some(Encoding::Cbor),
+            _ => None,
+        };
+    }
+
+    /// Returns the maximum size of the data of a single read or write with this [Encoding].
+    /// JSON spells every byte as a number of up to four characters,
+    /// so it carries half of [MAX_IO_SIZE] to stay well within a frame.
+    pub fn max_io_size(&self) -> u32 {
+        return match self {
+            Encoding::Json => MAX_IO_SIZE / 2,
+            Encoding::Cbor => MAX_IO_SIZE,
+        };
+    }
+
+    /// Serializes `obj` with this [Encoding]
+    pub fn encode<T: Serialize>(&self, obj: &T) -> Result<Vec<u8>, std::io::Error> {
+        let data = match self {
+            Encoding::Json => serde_json::to_vec(obj).map_err(|e| e.to_string()),
+            Encoding::Cbor => serde_cbor::to_vec(obj).map_err(|e| e.to_string()),
+        };
+        return data.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e));
+    }
+
+    /// Deserializes `data` with this [Encoding]
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, std::io::Error> {
+        let obj = match self {
+            Encoding::Json => serde_json::from_slice(data).map_err(|e| e.to_string()),
+            Encoding::Cbor => serde_cbor::from_slice(data).map_err(|e| e.to_string()),
+        };
+        return obj.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+    }
+}
@@ -0,0 +1,229 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// Kind of a failed call, independent of the errno numbering of the server
+pub enum ErrorKind {
+    NotFound,
+    PermissionDenied,
+    /// The operation is not permitted, EPERM
+    NotPermitted,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidInput,
+    NameTooLong,
+    ReadOnly,
+    /// The entry is on another filesystem, EXDEV
+    CrossesDevices,
+    NoSpace,
+    /// The inode is gone, ESTALE
+    Stale,
+    /// The handle is not open, EBADF
+    BadHandle,
+    /// A conflicting lock is held, EAGAIN
+    WouldBlock,
+    Deadlock,
+    Interrupted,
+    /// The Extended Attribute does not exist, ENODATA
+    NoData,
+    /// The buffer is too small, ERANGE
+    OutOfRange,
+    NotSupported,
+    /// The call is not implemented, ENOSYS
+    NotImplemented,
+    Busy,
+    /// Too many symlinks, ELOOP
+    Loop,
+    /// Unexpected or malformed message
+    Protocol,
+    Io,
+    /// Any other error, only described by its errno
+    Other,
+}
+
+impl ErrorKind {
+    /// Returns the [ErrorKind] of an errno of this platform
+    pub fn from_errno(errno: i32) -> ErrorKind {
+        return match errno {
+            libc::ENOENT => ErrorKind::NotFound,
+            libc::EACCES => ErrorKind::PermissionDenied,
+            libc::EPERM => ErrorKind::NotPermitted,
+            libc::EEXIST => ErrorKind::AlreadyExists,
+            libc::ENOTDIR => ErrorKind::NotADirectory,
+            libc::EISDIR => ErrorKind::IsADirectory,
+            libc::ENOTEMPTY => ErrorKind::DirectoryNotEmpty,
+            libc::EINVAL => ErrorKind::InvalidInput,
+            libc::ENAMETOOLONG => ErrorKind::NameTooLong,
+            libc::EROFS => ErrorKind::ReadOnly,
+            libc::EXDEV => ErrorKind::CrossesDevices,
+            libc::ENOSPC => ErrorKind::NoSpace,
+            libc::ESTALE => ErrorKind::Stale,
+            libc::EBADF => ErrorKind::BadHandle,
+            libc::EAGAIN => ErrorKind::WouldBlock,
+            libc::EDEADLK => ErrorKind::Deadlock,
+            libc::EINTR => ErrorKind::Interrupted,
+            libc::ENODATA => ErrorKind::NoData,
+            libc::ERANGE => ErrorKind::OutOfRange,
+            libc::ENOTSUP => ErrorKind::NotSupported,
+            libc::ENOSYS => ErrorKind::NotImplemented,
+            libc::EBUSY => ErrorKind::Busy,
+            libc::ELOOP => ErrorKind::Loop,
+            libc::EPROTO => ErrorKind::Protocol,
+            libc::EIO => ErrorKind::Io,
+            _ => ErrorKind::Other,
+        };
+    }
+
+    /// Returns the errno of this platform for the [ErrorKind], `None` for [ErrorKind::Other]
+    pub fn errno(&self) -> Option<i32> {
+        let errno = match self {
+            ErrorKind::NotFound => libc::ENOENT,
+            ErrorKind::PermissionDenied => libc::EACCES,
+            ErrorKind::NotPermitted => libc::EPERM,
+            ErrorKind::AlreadyExists => libc::EEXIST,
+            ErrorKind::NotADirectory => libc::ENOTDIR,
+            ErrorKind::IsADirectory => libc::EISDIR,
+            ErrorKind::DirectoryNotEmpty => libc::ENOTEMPTY,
+            ErrorKind::InvalidInput => libc::EINVAL,
+            ErrorKind::NameTooLong => libc::ENAMETOOLONG,
+            ErrorKind::ReadOnly => libc::EROFS,
+            ErrorKind::CrossesDevices => libc::EXDEV,
+            ErrorKind::NoSpace => libc::ENOSPC,
+            ErrorKind::Stale => libc::ESTALE,
+            ErrorKind::BadHandle => libc::EBADF,
+            ErrorKind::WouldBlock => libc::EAGAIN,
+            ErrorKind::Deadlock => libc::EDEADLK,
+            ErrorKind::Interrupted => libc::EINTR,
+            ErrorKind::NoData => libc::ENODATA,
+            ErrorKind::OutOfRange => libc::ERANGE,
+            ErrorKind::NotSupported => libc::ENOTSUP,
+            ErrorKind::NotImplemented => libc::ENOSYS,
+            ErrorKind::Busy => libc::EBUSY,
+            ErrorKind::Loop => libc::ELOOP,
+            ErrorKind::Protocol => libc::EPROTO,
+            ErrorKind::Io => libc::EIO,
+            ErrorKind::Other => return None,
+        };
+        return Some(errno);
+    }
+
+    /// Returns the [ErrorKind] of an [std::io::Error] carrying no errno
+    fn from_io_kind(kind: std::io::ErrorKind) -> ErrorKind {
+        return match kind {
+            std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+            std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            std::io::ErrorKind::AlreadyExists => ErrorKind::AlreadyExists,
+            std::io::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+            std::io::ErrorKind::InvalidData => ErrorKind::Protocol,
+            std::io::ErrorKind::WouldBlock => ErrorKind::WouldBlock,
+            std::io::ErrorKind::Interrupted => ErrorKind::Interrupted,
+            std::io::ErrorKind::Unsupported => ErrorKind::NotSupported,
+            _ => ErrorKind::Io,
+        };
+    }
+
+    /// Returns the closest [std::io::ErrorKind]
+    pub fn io_kind(&self) -> std::io::ErrorKind {
+        return match self {
+            ErrorKind::NotFound => std::io::ErrorKind::NotFound,
+            ErrorKind::PermissionDenied | ErrorKind::NotPermitted | ErrorKind::ReadOnly => {
+                std::io::ErrorKind::PermissionDenied
+            }
+            ErrorKind::AlreadyExists => std::io::ErrorKind::AlreadyExists,
+            ErrorKind::InvalidInput | ErrorKind::NameTooLong | ErrorKind::OutOfRange => {
+                std::io::ErrorKind::InvalidInput
+            }
+            ErrorKind::WouldBlock => std::io::ErrorKind::WouldBlock,
+            ErrorKind::Interrupted => std::io::ErrorKind::Interrupted,
+            ErrorKind::NotSupported | ErrorKind::NotImplemented => std::io::ErrorKind::Unsupported,
+            ErrorKind::Protocol => std::io::ErrorKind::InvalidData,
+            _ => std::io::ErrorKind::Other,
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Error of a failed call as sent by the server.
+///
+/// Clients should rely on `kind`, the errno is only meaningful on the platform of the server
+/// and is used for errors of [ErrorKind::Other].
+pub struct ShfsError {
+    pub kind: ErrorKind,
+    /// errno on the server
+    pub errno: i32,
+    /// Human readable description
+    pub message: String,
+    /// What failed, usually the name of the call
+    pub context: Option<String>,
+}
+
+impl ShfsError {
+    /// Returns a new [ShfsError] of `kind`
+    pub fn new(kind: ErrorKind, message: &str) -> ShfsError {
+        return ShfsError {
+            kind,
+            errno: kind.errno().unwrap_or(libc::EIO),
+            message: message.to_string(),
+            context: None,
+        };
+    }
+
+    /// Returns the [ShfsError] of an errno of this platform
+    pub fn from_errno(errno: i32) -> ShfsError {
+        return ShfsError {
+            kind: ErrorKind::from_errno(errno),
+            errno,
+            message: std::io::Error::from_raw_os_error(errno).to_string(),
+            context: None,
+        };
+    }
+
+    /// Returns the [ShfsError] describing an [std::io::Error].
+    /// Errors converted from a [ShfsError] give back the original.
+    pub fn from_io(err: &std::io::Error) -> ShfsError {
+        let inner = err.get_ref().and_then(|e| e.downcast_ref::<ShfsError>());
+        if inner.is_some() {
+            return inner.unwrap().clone();
+        }
+        if err.raw_os_error().is_some() {
+            return ShfsError::from_errno(err.raw_os_error().unwrap());
+        }
+        return ShfsError::new(ErrorKind::from_io_kind(err.kind()), &err.to_string());
+    }
+
+    /// Sets what failed
+    pub fn with_context(mut self, context: &str) -> ShfsError {
+        self.context = Some(context.to_string());
+        return self;
+    }
+
+    /// Returns the errno to report on this platform
+    pub fn local_errno(&self) -> i32 {
+        return self.kind.errno().unwrap_or(self.errno);
+    }
+}
+
+impl From<std::io::Error> for ShfsError {
+    fn from(err: std::io::Error) -> ShfsError {
+        return ShfsError::from_io(&err);
+    }
+}
+
+impl From<ShfsError> for std::io::Error {
+    fn from(err: ShfsError) -> std::io::Error {
+        return std::io::Error::new(err.kind.io_kind(), err);
+    }
+}
+
+impl fmt::Display for ShfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.context.is_some() {
+            return write!(f, "{}: {}", self.context.as_ref().unwrap(), self.message);
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ShfsError {}
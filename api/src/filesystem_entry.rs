@@ -1,5 +1,12 @@
 //use fuse::{FileAttr, FileType};
 use serde::{Deserialize, Serialize};
+#[cfg(target_os = "linux")]
+use std::os::linux::fs::MetadataExt;
+#[cfg(target_os = "macos")]
+use std::os::macos::fs::MetadataExt;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
 use time::Timespec;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,11 +21,31 @@ pub enum FsFiletype {
     Socket,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl FsFiletype {
+    /// Determines the [FsFiletype] from the `st_mode` of a file
+    pub fn from_mode(mode: u32) -> FsFiletype {
+        return match mode & 0o170000 {
+            0o010000 => FsFiletype::NamedPipe,
+            0o020000 => FsFiletype::CharDevice,
+            0o060000 => FsFiletype::BlockDevice,
+            0o040000 => FsFiletype::Directory,
+            0o120000 => FsFiletype::Symlink,
+            0o140000 => FsFiletype::Socket,
+            _ => FsFiletype::RegularFile,
+        };
+    }
+
+    /// Returns true for character and block devices
+    pub fn is_device(&self) -> bool {
+        return matches!(self, FsFiletype::CharDevice | FsFiletype::BlockDevice);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 /// Filesystem Timespec
 pub struct FsTimespec {
-    sec: i64,
-    nsec: i32,
+    pub sec: i64,
+    pub nsec: i32,
 }
 
 impl FsTimespec {
@@ -29,15 +56,75 @@ impl FsTimespec {
     pub fn ts(&self) -> Timespec {
         return Timespec::new(self.sec, self.nsec);
     }
+
+    pub fn from_ts(ts: &Timespec) -> FsTimespec {
+        return FsTimespec::new(ts.sec, ts.nsec);
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// Attribute changes applied by a setattr call. `None` leaves the attribute untouched.
+pub struct AttrChanges {
+    /// New permission bits
+    pub mode: Option<u32>,
+    /// New owner
+    pub uid: Option<u32>,
+    /// New group
+    pub gid: Option<u32>,
+    /// New size, truncating or extending the file
+    pub size: Option<u64>,
+    /// New access time
+    pub atime: Option<FsTimespec>,
+    /// New modification time
+    pub mtime: Option<FsTimespec>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Capacity of the filesystem backing a volume, as reported by statvfs
+pub struct FsStats {
+    /// Total blocks in units of `frsize`
+    pub blocks: u64,
+    /// Free blocks
+    pub bfree: u64,
+    /// Free blocks available to unprivileged users
+    pub bavail: u64,
+    /// Total inodes
+    pub files: u64,
+    /// Free inodes
+    pub ffree: u64,
+    /// Preferred block size
+    pub bsize: u32,
+    /// Maximum length of a filename
+    pub namelen: u32,
+    /// Fragment size, the unit of the block counts
+    pub frsize: u32,
+}
+
+impl FsStats {
+    /// Clamps the capacity to a quota of `quota` bytes of which `used` bytes are used by the volume.
+    /// Free space is limited by what is left of the quota as well as by the free space of the backing filesystem.
+    pub fn with_quota(mut self, quota: u64, used: u64) -> FsStats {
+        let frsize = std::cmp::max(self.frsize as u64, 1);
+        let quota_blocks = quota / frsize;
+        let used_blocks = used.div_ceil(frsize);
+        let left = quota_blocks.saturating_sub(used_blocks);
+        self.blocks = std::cmp::min(self.blocks, quota_blocks);
+        self.bfree = std::cmp::min(self.bfree, left);
+        self.bavail = std::cmp::min(self.bavail, left);
+        return self;
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Filesystem Object such as a Directory or a File
 pub struct FilesystemEntry {
-    /// Path of the entry
-    pub path: String,
+    /// Path of the entry, sent as raw bytes as it need not be valid UTF-8
+    #[serde(with = "crate::os_bytes")]
+    pub path: PathBuf,
     /// Inode of the entry
     pub ino: u64,
+    /// Generation of the inode, telling apart entries reusing an inode
+    pub generation: u64,
     /// Size of the entry
     pub size: u64,
     /// Blocks of the entry
@@ -56,12 +143,41 @@ pub struct FilesystemEntry {
     pub uid: u32,
     /// GID of the entry
     pub gid: u32,
+    /// Number of hard links to the entry
+    pub nlink: u32,
+    /// Device ID if the entry is a device node
+    pub rdev: u32,
     /// Kind of the entry
     pub kind: FsFiletype,
 }
 impl FilesystemEntry {
+    /// Creates a [FilesystemEntry] from the metadata of a file on the server
+    /// # Arguments
+    /// * `path` - Path of the entry relative to the volume root
+    /// * `m` - Metadata of the file
+    pub fn from_metadata(path: PathBuf, m: &std::fs::Metadata) -> FilesystemEntry {
+        let ctime = FsTimespec::new(m.st_ctime(), m.st_ctime_nsec() as i32);
+        return FilesystemEntry {
+            path,
+            ino: m.st_ino(),
+            generation: 0,
+            size: m.st_size(),
+            blocks: m.st_blocks(),
+            atime: FsTimespec::new(m.st_atime(), m.st_atime_nsec() as i32),
+            mtime: FsTimespec::new(m.st_mtime(), m.st_mtime_nsec() as i32),
+            ctime: ctime.clone(),
+            crtime: creation_time(m).unwrap_or(ctime),
+            perm: (m.st_mode() & 0o7777) as u16,
+            uid: m.st_uid(),
+            gid: m.st_gid(),
+            nlink: m.st_nlink() as u32,
+            rdev: m.st_rdev() as u32,
+            kind: FsFiletype::from_mode(m.st_mode()),
+        };
+    }
+
     pub fn new_file(
-        path: String,
+        path: PathBuf,
         ino: u64,
         size: u64,
         blocks: u64,
@@ -72,6 +188,7 @@ impl FilesystemEntry {
         return FilesystemEntry {
             path: path,
             ino: ino,
+            generation: 0,
             size: size,
             blocks: blocks,
             atime: FsTimespec::new(0, 0),
@@ -81,12 +198,14 @@ impl FilesystemEntry {
             perm: perm,
             uid: uid,
             gid: gid,
+            nlink: 1,
+            rdev: 0,
             kind: FsFiletype::RegularFile,
         };
     }
 
     pub fn new_directory(
-        path: String,
+        path: PathBuf,
         ino: u64,
         size: u64,
         blocks: u64,
@@ -97,6 +216,7 @@ impl FilesystemEntry {
         return FilesystemEntry {
             path: path,
             ino: ino,
+            generation: 0,
             size: size,
             blocks: blocks,
             atime: FsTimespec::new(0, 0),
@@ -106,7 +226,36 @@ impl FilesystemEntry {
             perm: perm,
             uid: uid,
             gid: gid,
+            nlink: 2,
+            rdev: 0,
             kind: FsFiletype::Directory,
         };
     }
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Entry of a directory listing together with its attributes
+pub struct DirEntry {
+    /// Name of the entry inside the directory, `.` and `..` for the directory and its parent
+    #[serde(with = "crate::os_bytes")]
+    pub name: OsString,
+    pub entry: FilesystemEntry,
+}
+
+/// Returns the creation time of a file if the platform records it
+pub(crate) fn creation_time(m: &std::fs::Metadata) -> Option<FsTimespec> {
+    let created = m.created().ok()?;
+    return match created.duration_since(UNIX_EPOCH) {
+        Ok(d) => Some(FsTimespec::new(d.as_secs() as i64, d.subsec_nanos() as i32)),
+        Err(e) => {
+            let d = e.duration();
+            let mut sec = -(d.as_secs() as i64);
+            let mut nsec = d.subsec_nanos() as i32;
+            if nsec > 0 {
+                sec -= 1;
+                nsec = 1_000_000_000 - nsec;
+            }
+            Some(FsTimespec::new(sec, nsec))
+        }
+    };
+}
@@ -0,0 +1,76 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Maximum size of the payload of a single frame (64 MiB)
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// Size of the length header preceding every frame
+const HEADER_SIZE: usize = 4;
+
+/// Writes `payload` as a single frame to `stream`.
+///
+/// A frame consists of the payload length as a big endian `u32` followed by the payload itself.
+/// # Arguments
+/// * `stream` - The stream to write to
+/// * `payload` - The serialized message
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    payload: &[u8],
+) -> Result<(), std::io::Error> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Frame exceeds maximum frame size",
+        ));
+    }
+    let header = (payload.len() as u32).to_be_bytes();
+    let mut buf = Vec::with_capacity(HEADER_SIZE + payload.len());
+    buf.extend_from_slice(&header);
+    buf.extend_from_slice(payload);
+    stream.write_all(&buf).await?;
+    stream.flush().await?;
+    return Ok(());
+}
+
+/// Reads a single frame from `stream` returning its payload.
+///
+/// Returns `None` if the stream was closed cleanly before a new frame started.
+/// # Arguments
+/// * `stream` - The stream to read from
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<Option<Vec<u8>>, std::io::Error> {
+    let mut header = [0; HEADER_SIZE];
+    let mut filled = 0;
+    while filled < HEADER_SIZE {
+        let n = stream.read(&mut header[filled..]).await?;
+        if n == 0 {
+            if filled == 0 {
+                return Ok(None);
+            }
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Connection closed inside frame header",
+            ));
+        }
+        filled += n;
+    }
+
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Frame exceeds maximum frame size",
+        ));
+    }
+
+    // Growing with the data received, a header alone does not allocate the whole frame
+    let mut payload = Vec::new();
+    stream.take(len as u64).read_to_end(&mut payload).await?;
+    if payload.len() < len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "Connection closed inside frame",
+        ));
+    }
+    return Ok(Some(payload));
+}
@@ -0,0 +1,628 @@
+use crate::filesystem_entry::FsTimespec;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Inode id of the volume root
+pub(crate) const ROOT_ID: u64 = 1;
+
+/// Ids reserved in the state file at once, so ids are never handed out twice even after a crash
+const ID_BLOCK: u64 = 1024;
+
+/// Lines the state file grows to at least before it is compacted.
+/// Beyond that it is compacted once more than half of its lines are outdated.
+const COMPACT_MIN_LINES: usize = 4096;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Inode of a volume, identified by device and inode number of the file on the server
+struct InodeRecord {
+    id: u64,
+    dev: u64,
+    ino: u64,
+    /// Bumped whenever the inode number on the server is reused by another file
+    generation: u64,
+    /// Creation time of the file, telling apart files reusing an inode number
+    btime: Option<FsTimespec>,
+    /// Every path the inode was seen under, it can have several if it has hard links
+    #[serde(with = "crate::os_bytes::list")]
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+/// Line of the state file
+enum LogEntry {
+    /// Every id below is used or reserved
+    NextId(u64),
+    /// Adds or replaces an inode
+    Set(InodeRecord),
+    /// Forgets an inode, its id is stale from now on
+    Remove(u64),
+}
+
+/// Change waiting to be written to the state file
+enum Change {
+    /// Appends a line
+    Append(LogEntry),
+    /// Replaces the whole file with a compacted one
+    Rewrite(Vec<LogEntry>),
+}
+
+/// State file an [InodeCache] is kept in.
+///
+/// Changes are queued while the inode table is locked and written by [flush](InodeLog::flush) once it is unlocked,
+/// so lookups are not held up by writing the file.
+pub(crate) struct InodeLog {
+    path: PathBuf,
+    file: Mutex<fs::File>,
+    /// Changes not written yet, in the order they were made
+    queue: Mutex<Vec<Change>>,
+}
+
+impl InodeLog {
+    /// Writes every queued change to the state file.
+    /// Ids reserved by the changes are on disk once this returns.
+    pub fn flush(&self) {
+        let mut file = self.file.lock().unwrap();
+        // Taken while the file is locked, so changes are written in order
+        let changes = std::mem::take(&mut *self.queue.lock().unwrap());
+        let err = self.write(&mut file, changes);
+        if err.is_err() {
+            eprintln!("Error writing inode table: {}", err.unwrap_err());
+        }
+    }
+
+    fn write(&self, file: &mut fs::File, changes: Vec<Change>) -> Result<(), std::io::Error> {
+        let mut sync = false;
+        for change in changes {
+            match change {
+                Change::Append(entry) => {
+                    sync = sync || matches!(entry, LogEntry::NextId(_));
+                    write_entry(file, &entry)?;
+                }
+                Change::Rewrite(entries) => {
+                    *file = rewrite(&self.path, &entries)?;
+                }
+            }
+        }
+        if sync {
+            file.sync_data()?;
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for InodeLog {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// What is known about an inode id
+pub(crate) enum InodeState {
+    /// The inode exists, last seen under the path
+    Live(PathBuf),
+    /// The id was handed out before, but the inode is gone
+    Stale,
+    /// The id was never handed out
+    Unknown,
+}
+
+/// Table mapping files on the server to the stable inode ids seen by clients.
+///
+/// If a state file is used the table survives restarts of the server.
+/// It is an append-only log of changes which is compacted when loaded and once most of its lines are outdated.
+/// Without a state file ids start at a point in time derived offset,
+/// so ids of earlier runs are reported as stale instead of referring to other files.
+pub(crate) struct InodeCache {
+    records: HashMap<u64, InodeRecord>,
+    /// Maps device and inode number to the inode id
+    ids: HashMap<(u64, u64), u64>,
+    /// Maps every known path to its inode id.
+    /// Paths are ordered by component, so a directory is followed by its descendants.
+    index: BTreeMap<PathBuf, u64>,
+    next_id: u64,
+    /// First id not reserved in the state file
+    reserved: u64,
+    log: Option<Arc<InodeLog>>,
+    /// Lines in the state file, including outdated ones
+    lines: usize,
+}
+
+impl InodeCache {
+    /// Returns an [InodeCache] only kept in memory
+    pub fn new() -> InodeCache {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        return InodeCache {
+            records: HashMap::new(),
+            ids: HashMap::new(),
+            index: BTreeMap::new(),
+            next_id: std::cmp::max(ROOT_ID + 1, now << 24),
+            reserved: 0,
+            log: None,
+            lines: 0,
+        };
+    }
+
+    /// Loads the [InodeCache] stored in `state`, creating it if it does not exist
+    pub fn open(state: &Path) -> Result<InodeCache, std::io::Error> {
+        let mut cache = InodeCache {
+            records: HashMap::new(),
+            ids: HashMap::new(),
+            index: BTreeMap::new(),
+            next_id: ROOT_ID + 1,
+            reserved: 0,
+            log: None,
+            lines: 0,
+        };
+        if state.exists() {
+            let file = fs::File::open(state)?;
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                // A line cut short by a crash is skipped
+                let entry: Result<LogEntry, _> = serde_json::from_str(&line);
+                if entry.is_ok() {
+                    cache.replay(entry.unwrap());
+                }
+            }
+        } else if state.parent().is_some() {
+            fs::create_dir_all(state.parent().unwrap())?;
+        }
+
+        for record in cache.records.values() {
+            for path in &record.paths {
+                cache.index.insert(path.clone(), record.id);
+            }
+        }
+
+        cache.reserved = cache.next_id;
+        let entries = cache.snapshot();
+        let file = rewrite(state, &entries)?;
+        cache.lines = entries.len();
+        cache.log = Some(Arc::new(InodeLog {
+            path: state.to_path_buf(),
+            file: Mutex::new(file),
+            queue: Mutex::new(vec![]),
+        }));
+        return Ok(cache);
+    }
+
+    /// Returns the state file, if the table is kept in one
+    pub fn log(&self) -> Option<Arc<InodeLog>> {
+        return self.log.clone();
+    }
+
+    /// Returns the lines of a compacted state file holding the table
+    fn snapshot(&self) -> Vec<LogEntry> {
+        let mut entries = vec![LogEntry::NextId(std::cmp::max(self.reserved, self.next_id))];
+        for record in self.records.values() {
+            entries.push(LogEntry::Set(record.clone()));
+        }
+        return entries;
+    }
+
+    fn replay(&mut self, entry: LogEntry) {
+        match entry {
+            LogEntry::NextId(id) => {
+                self.next_id = std::cmp::max(self.next_id, id);
+            }
+            LogEntry::Set(record) => {
+                self.next_id = std::cmp::max(self.next_id, record.id + 1);
+                self.forget(record.id);
+                self.ids.insert((record.dev, record.ino), record.id);
+                self.records.insert(record.id, record);
+            }
+            LogEntry::Remove(id) => {
+                self.next_id = std::cmp::max(self.next_id, id + 1);
+                self.forget(id);
+            }
+        }
+    }
+
+    /// Queues a change for the state file, compacting it once most of its lines are outdated
+    fn persist(&mut self, entry: LogEntry) {
+        if self.log.is_none() {
+            return;
+        }
+        let log = self.log.clone().unwrap();
+        log.queue.lock().unwrap().push(Change::Append(entry));
+        self.lines += 1;
+        if self.lines > COMPACT_MIN_LINES && self.lines > 2 * self.records.len() {
+            let entries = self.snapshot();
+            self.lines = entries.len();
+            log.queue.lock().unwrap().push(Change::Rewrite(entries));
+        }
+    }
+
+    /// Removes an inode from memory only
+    fn forget(&mut self, id: u64) {
+        let record = self.records.remove(&id);
+        if record.is_some() {
+            let record = record.unwrap();
+            if self.ids.get(&(record.dev, record.ino)) == Some(&id) {
+                self.ids.remove(&(record.dev, record.ino));
+            }
+            for path in &record.paths {
+                if self.index.get(path) == Some(&id) {
+                    self.index.remove(path);
+                }
+            }
+        }
+    }
+
+    /// Makes `path` refer to the inode, detaching it from the inode it referred to before
+    fn claim_path(&mut self, id: u64, path: &Path) {
+        let previous = self.index.insert(path.to_path_buf(), id);
+        if previous.is_some() && previous.unwrap() != id {
+            self.detach(previous.unwrap(), path);
+        }
+    }
+
+    /// Removes `path` from the paths of the inode, removing the inode once it has none
+    fn detach(&mut self, id: u64, path: &Path) {
+        let record = self.records.get_mut(&id);
+        if record.is_none() {
+            return;
+        }
+        let record = record.unwrap();
+        record.paths.retain(|p| p != path);
+        if record.paths.is_empty() && id != ROOT_ID {
+            self.remove(id);
+            return;
+        }
+        let record = record.clone();
+        self.persist(LogEntry::Set(record));
+    }
+
+    /// Returns a new id, reserving the next block of ids in the state file if necessary
+    fn allocate(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        if self.log.is_some() && self.next_id > self.reserved {
+            self.reserved = self.next_id + ID_BLOCK;
+            let reserved = self.reserved;
+            self.persist(LogEntry::NextId(reserved));
+        }
+        return id;
+    }
+
+    /// Maps the volume root to [ROOT_ID]
+    pub fn set_root(&mut self, dev: u64, ino: u64, btime: Option<FsTimespec>) {
+        let record = self.records.get(&ROOT_ID);
+        if record.is_some() {
+            let record = record.unwrap();
+            if record.dev == dev && record.ino == ino {
+                return;
+            }
+        }
+        let previous = self.ids.get(&(dev, ino)).copied();
+        if previous.is_some() {
+            self.remove(previous.unwrap());
+        }
+        self.forget(ROOT_ID);
+        self.index.insert(PathBuf::from("/"), ROOT_ID);
+        let record = InodeRecord {
+            id: ROOT_ID,
+            dev,
+            ino,
+            generation: 0,
+            btime,
+            paths: vec![PathBuf::from("/")],
+        };
+        self.ids.insert((dev, ino), ROOT_ID);
+        self.records.insert(ROOT_ID, record.clone());
+        self.persist(LogEntry::Set(record));
+    }
+
+    /// Returns the inode id and generation of a file, remembering `path` as one of its paths.
+    /// A file reusing the inode number of a removed one gets a new id.
+    pub fn lookup(&mut self, dev: u64, ino: u64, btime: Option<FsTimespec>, path: &Path) -> (u64, u64) {
+        let mut generation = 0;
+        let id = self.ids.get(&(dev, ino)).copied();
+        if id.is_some() {
+            let id = id.unwrap();
+            let record = &self.records[&id];
+            if id == ROOT_ID || record.btime.is_none() || btime.is_none() || record.btime == btime {
+                self.claim_path(id, path);
+                let record = self.records.get_mut(&id).unwrap();
+                let mut changed = false;
+                if !record.paths.iter().any(|p| p == path) {
+                    record.paths.push(path.to_path_buf());
+                    changed = true;
+                }
+                if record.btime.is_none() && btime.is_some() {
+                    record.btime = btime;
+                    changed = true;
+                }
+                let generation = record.generation;
+                if changed {
+                    let record = record.clone();
+                    self.persist(LogEntry::Set(record));
+                }
+                return (id, generation);
+            }
+            // The inode number now belongs to another file
+            generation = record.generation + 1;
+            self.remove(id);
+        }
+        let record = InodeRecord {
+            id: self.allocate(),
+            dev,
+            ino,
+            generation,
+            btime,
+            paths: vec![path.to_path_buf()],
+        };
+        self.claim_path(record.id, path);
+        self.ids.insert((dev, ino), record.id);
+        self.records.insert(record.id, record.clone());
+        self.persist(LogEntry::Set(record.clone()));
+        return (record.id, generation);
+    }
+
+    /// Returns the inode id and generation of a file if it is known under `path` already,
+    /// in which case [lookup](InodeCache::lookup) would not change anything
+    pub fn get(&self, dev: u64, ino: u64, btime: Option<&FsTimespec>, path: &Path) -> Option<(u64, u64)> {
+        let id = *self.ids.get(&(dev, ino))?;
+        let record = &self.records[&id];
+        let same_file = id == ROOT_ID || btime.is_none() || record.btime.as_ref() == btime;
+        if !same_file || self.index.get(path) != Some(&id) || !record.paths.iter().any(|p| p == path) {
+            return None;
+        }
+        return Some((id, record.generation));
+    }
+
+    /// Returns what is known about an inode id
+    pub fn state(&self, id: u64) -> InodeState {
+        let record = self.records.get(&id);
+        if record.is_some() {
+            let record = record.unwrap();
+            if !record.paths.is_empty() {
+                return InodeState::Live(record.paths[0].clone());
+            }
+        }
+        if id < self.next_id {
+            return InodeState::Stale;
+        }
+        return InodeState::Unknown;
+    }
+
+    /// Forgets an inode, its id is stale from now on
+    pub fn remove(&mut self, id: u64) {
+        self.forget(id);
+        self.persist(LogEntry::Remove(id));
+    }
+
+    /// Forgets `path` as a path of the inode.
+    /// If no other path is known the inode is removed.
+    pub fn remove_path(&mut self, id: u64, path: &Path) {
+        if self.index.get(path) == Some(&id) {
+            self.index.remove(path);
+        }
+        self.detach(id, path);
+    }
+
+    /// Returns every known path equal to or below `path` with its inode id
+    fn tree(&self, path: &Path) -> Vec<(PathBuf, u64)> {
+        let mut ret = vec![];
+        for (p, id) in self.index.range(path.to_path_buf()..) {
+            if !p.starts_with(path) {
+                break;
+            }
+            ret.push((p.clone(), *id));
+        }
+        return ret;
+    }
+
+    /// Forgets `path` and every path below it
+    pub fn remove_tree(&mut self, path: &Path) {
+        for (p, id) in self.tree(path) {
+            self.remove_path(id, &p);
+        }
+    }
+
+    /// Moves `from` and every path below it to `to`, replacing whatever was known at `to`
+    pub fn rename(&mut self, from: &Path, to: &Path) {
+        if from == to {
+            return;
+        }
+        let moved = self.tree(from);
+        self.remove_tree(to);
+        for (path, id) in moved {
+            let rest = path.strip_prefix(from).unwrap();
+            let mut new_path = to.to_path_buf();
+            if !rest.as_os_str().is_empty() {
+                new_path.push(rest);
+            }
+            self.index.remove(&path);
+            self.index.insert(new_path.clone(), id);
+            let record = self.records.get_mut(&id);
+            if record.is_none() {
+                continue;
+            }
+            let record = record.unwrap();
+            for p in record.paths.iter_mut() {
+                if *p == path {
+                    *p = new_path.clone();
+                }
+            }
+            let record = record.clone();
+            self.persist(LogEntry::Set(record));
+        }
+    }
+}
+
+/// Replaces the state file at `path` with `entries`, returning it opened for appending
+fn rewrite(path: &Path, entries: &[LogEntry]) -> Result<fs::File, std::io::Error> {
+    let tmp = path.with_extension("tmp");
+    let mut out = fs::File::create(&tmp)?;
+    for entry in entries {
+        write_entry(&mut out, entry)?;
+    }
+    out.sync_all()?;
+    fs::rename(&tmp, path)?;
+    return fs::OpenOptions::new().append(true).open(path);
+}
+
+/// Writes one line to the state file
+fn write_entry(file: &mut fs::File, entry: &LogEntry) -> Result<(), std::io::Error> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    return file.write_all(&line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn btime(sec: i64) -> Option<FsTimespec> {
+        return Some(FsTimespec { sec, nsec: 0 });
+    }
+
+    /// Opens the table in `state` with the volume root on device 1 and inode 2
+    fn open(state: &Path) -> InodeCache {
+        let mut cache = InodeCache::open(state).unwrap();
+        cache.set_root(1, 2, btime(1));
+        cache.log().unwrap().flush();
+        return cache;
+    }
+
+    fn lines(state: &Path) -> usize {
+        return fs::read_to_string(state).unwrap().lines().count();
+    }
+
+    #[test]
+    fn ids_survive_a_restart() {
+        let dir = TempDir::new().unwrap();
+        let state = dir.path().join("inodes");
+        let mut cache = open(&state);
+        let (file, _) = cache.lookup(1, 10, btime(5), Path::new("/file"));
+        let (gone, _) = cache.lookup(1, 11, btime(5), Path::new("/gone"));
+        cache.remove_path(gone, Path::new("/gone"));
+        drop(cache);
+
+        let mut cache = open(&state);
+        assert!(matches!(cache.state(file), InodeState::Live(p) if p == Path::new("/file")));
+        assert!(matches!(cache.state(gone), InodeState::Stale));
+        assert_eq!(cache.lookup(1, 10, btime(5), Path::new("/file")), (file, 0));
+        // Ids are not handed out twice
+        let (new, _) = cache.lookup(1, 12, btime(5), Path::new("/new"));
+        assert!(new > gone);
+    }
+
+    #[test]
+    fn unknown_and_stale_ids() {
+        let mut cache = InodeCache::new();
+        cache.set_root(1, 2, btime(1));
+        assert!(matches!(cache.state(ROOT_ID), InodeState::Live(p) if p == Path::new("/")));
+        let (id, _) = cache.lookup(1, 10, btime(5), Path::new("/file"));
+        cache.remove(id);
+        assert!(matches!(cache.state(id), InodeState::Stale));
+        assert!(matches!(cache.state(id + 1), InodeState::Unknown));
+    }
+
+    #[test]
+    fn reused_inode_numbers_get_a_new_generation() {
+        let mut cache = InodeCache::new();
+        cache.set_root(1, 2, btime(1));
+        let (id, generation) = cache.lookup(1, 10, btime(5), Path::new("/file"));
+        assert_eq!(generation, 0);
+        assert_eq!(cache.get(1, 10, btime(5).as_ref(), Path::new("/file")), Some((id, 0)));
+        assert_eq!(cache.get(1, 10, btime(6).as_ref(), Path::new("/file")), None);
+
+        let (new, generation) = cache.lookup(1, 10, btime(6), Path::new("/file"));
+        assert_ne!(new, id);
+        assert_eq!(generation, 1);
+        assert!(matches!(cache.state(id), InodeState::Stale));
+    }
+
+    /// Returns a table holding `/dir`, `/dir/sub` and `/dir/sub/file` with their ids
+    fn tree() -> (InodeCache, [u64; 3]) {
+        let mut cache = InodeCache::new();
+        cache.set_root(1, 2, btime(1));
+        let (dir, _) = cache.lookup(1, 10, btime(5), Path::new("/dir"));
+        let (sub, _) = cache.lookup(1, 11, btime(5), Path::new("/dir/sub"));
+        let (file, _) = cache.lookup(1, 12, btime(5), Path::new("/dir/sub/file"));
+        return (cache, [dir, sub, file]);
+    }
+
+    #[test]
+    fn renamed_directories_keep_their_descendants() {
+        let (mut cache, ids) = tree();
+        // Sorting right after `/dir` but not below it
+        let (sibling, _) = cache.lookup(1, 13, btime(5), Path::new("/dir-sibling"));
+        cache.rename(Path::new("/dir"), Path::new("/moved"));
+
+        let moved = [(10, "/moved"), (11, "/moved/sub"), (12, "/moved/sub/file")];
+        for (id, (ino, path)) in ids.iter().zip(moved.iter()) {
+            assert!(matches!(cache.state(*id), InodeState::Live(p) if p == Path::new(path)));
+            assert_eq!(cache.get(1, *ino, btime(5).as_ref(), Path::new(path)), Some((*id, 0)));
+            assert_eq!(cache.lookup(1, *ino, btime(5), Path::new(path)), (*id, 0));
+        }
+        assert_eq!(cache.get(1, 12, btime(5).as_ref(), Path::new("/dir/sub/file")), None);
+        assert!(matches!(cache.state(sibling), InodeState::Live(p) if p == Path::new("/dir-sibling")));
+    }
+
+    #[test]
+    fn renaming_over_a_directory_replaces_it() {
+        let (mut cache, ids) = tree();
+        let (target, _) = cache.lookup(1, 20, btime(5), Path::new("/target"));
+        let (below, _) = cache.lookup(1, 21, btime(5), Path::new("/target/old"));
+        cache.rename(Path::new("/dir/sub"), Path::new("/target"));
+
+        assert!(matches!(cache.state(ids[1]), InodeState::Live(p) if p == Path::new("/target")));
+        assert!(matches!(cache.state(ids[2]), InodeState::Live(p) if p == Path::new("/target/file")));
+        assert!(matches!(cache.state(target), InodeState::Stale));
+        assert!(matches!(cache.state(below), InodeState::Stale));
+    }
+
+    #[test]
+    fn removed_trees_are_dropped() {
+        let (mut cache, ids) = tree();
+        let (kept, _) = cache.lookup(1, 13, btime(5), Path::new("/dir-sibling"));
+        cache.remove_tree(Path::new("/dir"));
+
+        for id in &ids {
+            assert!(matches!(cache.state(*id), InodeState::Stale));
+        }
+        assert_eq!(cache.get(1, 12, btime(5).as_ref(), Path::new("/dir/sub/file")), None);
+        assert!(matches!(cache.state(kept), InodeState::Live(_)));
+        assert!(matches!(cache.state(ROOT_ID), InodeState::Live(_)));
+    }
+
+    #[test]
+    fn hard_links_outlive_a_removed_tree() {
+        let (mut cache, ids) = tree();
+        cache.lookup(1, 12, btime(5), Path::new("/link"));
+        cache.remove_tree(Path::new("/dir"));
+
+        assert!(matches!(cache.state(ids[2]), InodeState::Live(p) if p == Path::new("/link")));
+        assert!(matches!(cache.state(ids[0]), InodeState::Stale));
+    }
+
+    #[test]
+    fn log_is_compacted_as_it_grows() {
+        let dir = TempDir::new().unwrap();
+        let state = dir.path().join("inodes");
+        let mut cache = open(&state);
+        let (id, _) = cache.lookup(1, 10, btime(5), Path::new("/a"));
+        for i in 0..COMPACT_MIN_LINES {
+            let from = format!("/{}", ["a", "b"][i % 2]);
+            let to = format!("/{}", ["b", "a"][i % 2]);
+            cache.rename(Path::new(&from), Path::new(&to));
+            cache.log().unwrap().flush();
+        }
+        assert!(lines(&state) < COMPACT_MIN_LINES);
+        drop(cache);
+
+        let cache = open(&state);
+        assert!(matches!(cache.state(id), InodeState::Live(p) if p == Path::new("/a")));
+    }
+}
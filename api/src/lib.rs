@@ -1,21 +1,35 @@
-use path_absolutize::*;
 use rich::unwrap_or_err;
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::error::Error;
 use std::fmt;
 use std::fs;
-use std::io::{Read, Seek, SeekFrom, Write};
 #[cfg(target_os = "linux")]
 use std::os::linux::fs::MetadataExt;
 #[cfg(target_os = "macos")]
 use std::os::macos::fs::MetadataExt;
-use std::path::Path;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use inode_cache::{InodeCache, InodeLog, InodeState};
+use resolve::{Location, RootDir};
 
 pub mod filesystem_entry;
+mod inode_cache;
 pub mod calls;
 pub mod config;
+pub mod encoding;
+pub mod error;
+pub mod framing;
+pub mod lock;
+mod os_bytes;
+pub mod protocol;
+mod resolve;
 pub mod responses;
 pub mod volume;
+pub mod xattr;
 
 #[derive(Debug)]
 pub struct ApiError {
@@ -39,78 +53,134 @@ impl fmt::Display for ApiError {
 impl Error for ApiError {}
 
 /// Server Side API handling Volume Requests
+///
+/// The API is shared between all connections, so every method takes `&self`.
 pub struct FilesystemAPI {
     pub root: String,
-    inode_cache: HashMap<u64, filesystem_entry::FilesystemEntry>,
+    /// The open root directory all entries are resolved beneath
+    root_dir: RootDir,
+    inode_cache: RwLock<InodeCache>,
+    /// State file the inode table is kept in, written after the table is unlocked
+    inode_log: Option<Arc<InodeLog>>,
+    /// Device of the volume root
+    root_dev: u64,
+    /// If entries on other filesystems mounted inside the volume are accessible
+    cross_mounts: bool,
+    /// Last walked usage of the volume with the time it was walked at
+    usage: Mutex<Option<(Instant, u64)>>,
 }
 
+/// How long the walked usage of a volume is reused by [FilesystemAPI::cached_usage]
+const USAGE_TTL: Duration = Duration::from_secs(10);
+
 impl FilesystemAPI {
+    /// Returns a [FilesystemAPI] keeping its inode table in memory only
     pub fn new(root: String) -> FilesystemAPI {
+        return FilesystemAPI::with_inode_cache(root, InodeCache::new());
+    }
+
+    /// Returns a [FilesystemAPI] storing its inode table in `state`,
+    /// so inodes stay valid across restarts of the server
+    pub fn with_state(root: String, state: &str) -> FilesystemAPI {
+        let cache = unwrap_or_err(InodeCache::open(Path::new(state)), "Can not load inode table");
+        return FilesystemAPI::with_inode_cache(root, cache);
+    }
+
+    fn with_inode_cache(root: String, cache: InodeCache) -> FilesystemAPI {
+        let root_dir = unwrap_or_err(RootDir::open(&root), "Can not get root dir on server");
         let mut api = FilesystemAPI {
             root,
-            inode_cache: HashMap::new(),
+            root_dir,
+            inode_log: cache.log(),
+            inode_cache: RwLock::new(cache),
+            root_dev: 0,
+            cross_mounts: true,
+            usage: Mutex::new(None),
         };
-        let mut ret = unwrap_or_err(api.get_entry("/"), "Can not get root dir on server");
-        ret.ino = 1;
-        api.inode_cache.insert(1, ret.clone());
+        let rpath = unwrap_or_err(api.resolve(Path::new("/")), "Can not get root dir on server");
+        let m = unwrap_or_err(rpath.metadata(), "Can not get root dir on server");
+        let btime = filesystem_entry::creation_time(&m);
+        api.root_dev = m.st_dev();
+        api.update_inode_table(|cache| cache.set_root(m.st_dev(), m.st_ino(), btime));
 
         return api;
     }
 
-    /// Combine path with the root path of the Volume
-    fn join_root_path(&self, path: &str) -> Result<String, &str> {
-        let rpath = Path::new(&self.root);
-        let mut newpath = String::new();
-        for e in rpath.components() {
-            let comp_str = e.as_os_str().to_str().expect("");
-            newpath.push_str(comp_str);
-            if comp_str != "/" {
-                newpath.push_str("/");
-            }
-        }
-        for e in Path::new(path).components() {
-            let comp_str = e.as_os_str().to_str().expect("");
-            newpath.push_str(comp_str);
-            if comp_str != "/" {
-                newpath.push_str("/");
-            }
+    /// Runs `f` with the inode table locked for writing.
+    /// The changes are written to the state file once the table is unlocked again.
+    fn update_inode_table<T>(&self, f: impl FnOnce(&mut InodeCache) -> T) -> T {
+        let ret = f(&mut self.inode_cache.write().unwrap());
+        if self.inode_log.is_some() {
+            self.inode_log.as_ref().unwrap().flush();
         }
-        newpath = newpath.replace("//", "/");
-        if newpath.ends_with("/") {
-            newpath.pop();
-        }
-        let p = Path::new(&newpath);
-        newpath = String::from(p.absolutize().unwrap().to_str().unwrap());
-        if !Path::new(&newpath).starts_with(Path::new(&self.root)) {
-            return Err("Root Escalation");
-        }
-        return Ok(newpath);
+        return ret;
+    }
+
+    /// Sets if entries on other filesystems mounted inside the volume are accessible.
+    /// If not, they fail with EXDEV.
+    pub fn set_cross_mounts(&mut self, cross_mounts: bool) {
+        self.cross_mounts = cross_mounts;
+        self.root_dir.set_cross_mounts(cross_mounts);
+    }
+
+    /// Resolves `path` beneath the root of the Volume, see [resolve](resolve).
+    /// Fails with EACCES if the path leads outside of the root.
+    fn resolve(&self, path: &Path) -> Result<Location, std::io::Error> {
+        return self.root_dir.resolve(path);
     }
 
-    pub fn readdir(&self, path: &str) -> Vec<String> {
+    /// Resolves the path of an inode beneath the root of the Volume
+    fn resolve_inode(&self, ino: u64) -> Result<Location, std::io::Error> {
+        return self.resolve(&self.get_path_from_inode(ino)?);
+    }
+
+    /// Returns the paths of the entries of a directory
+    pub fn readdir(&self, path: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
         let mut ret = vec![];
-        let rpath = self.join_root_path(path);
-        if rpath.is_err() {
-            return ret;
+        let dir = self.root_dir.resolve_dir(path)?;
+
+        for entry in dir.read_dir()? {
+            ret.push(Path::new("/").join(path).join(entry.name()));
         }
-        let rpath = rpath.unwrap();
+        return Ok(ret);
+    }
 
-        // TODO : Maybe error handling?
-        let entries = std::fs::read_dir(rpath);
-        if entries.is_err() {
-            return ret;
+    /// Returns the entries of the directory `ino` with their attributes, starting with `.` and `..`.
+    /// The parent of the volume root is the root itself.
+    /// Entries which can not be looked up, e.g. because they were removed meanwhile, are skipped.
+    pub fn readdirplus(&self, ino: u64) -> Result<Vec<filesystem_entry::DirEntry>, std::io::Error> {
+        let dir = self.get_entry_from_inode(ino)?;
+        if !matches!(dir.kind, filesystem_entry::FsFiletype::Directory) {
+            return Err(std::io::Error::from_raw_os_error(libc::ENOTDIR));
         }
-        let entries = entries.unwrap();
-        for entry in entries {
-            let entry = entry.expect("Error getting entry");
-            let path = entry.path();
-            let pathstr = path.to_str().expect("Error converting to str");
-            ret.push(String::from(pathstr.replace(&self.root, "")));
+        let parent = match dir.path.parent() {
+            Some(parent) => self.get_entry(parent)?,
+            None => dir.clone(),
+        };
+        let mut ret = vec![
+            filesystem_entry::DirEntry {
+                name: ".".into(),
+                entry: dir.clone(),
+            },
+            filesystem_entry::DirEntry {
+                name: "..".into(),
+                entry: parent,
+            },
+        ];
+        for path in self.readdir(&dir.path)? {
+            let entry = self.get_entry(&path);
+            if entry.is_err() || path.file_name().is_none() {
+                continue;
+            }
+            ret.push(filesystem_entry::DirEntry {
+                name: path.file_name().unwrap().to_os_string(),
+                entry: entry.unwrap(),
+            });
         }
-        return ret;
+        return Ok(ret);
     }
 
-    pub fn get_path_from_inode(&self, ino: u64) -> Result<String, std::io::Error> {
+    pub fn get_path_from_inode(&self, ino: u64) -> Result<PathBuf, std::io::Error> {
         let parent_ino = self.get_entry_from_inode(ino);
         if parent_ino.is_err() {
             return Err(parent_ino.unwrap_err());
@@ -118,239 +188,425 @@ impl FilesystemAPI {
         return Ok(parent_ino.unwrap().path);
     }
 
+    /// Returns the path of `name` inside the directory `parent`
+    fn child_path(&self, parent: u64, name: &OsStr) -> Result<PathBuf, std::io::Error> {
+        return Ok(self.get_path_from_inode(parent)?.join(name));
+    }
+
+    /// Returns the current [FilesystemEntry](filesystem_entry::FilesystemEntry) of an inode.
+    /// Every known path of the inode is tried until one still refers to it.
+    /// Fails with ESTALE if the inode is gone and with ENOENT if it never existed.
     pub fn get_entry_from_inode(
         &self,
         ino: u64,
     ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
-        let res = self.inode_cache.get(&ino);
-        if res.is_some() {
-            return Ok(res.unwrap().clone());
-        } else {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Not Found",
-            ));
+        loop {
+            let path = match self.inode_cache.read().unwrap().state(ino) {
+                InodeState::Live(path) => path,
+                InodeState::Stale => return Err(std::io::Error::from_raw_os_error(libc::ESTALE)),
+                InodeState::Unknown => return Err(std::io::Error::from_raw_os_error(libc::ENOENT)),
+            };
+            match self.get_entry(&path) {
+                Ok(entry) if entry.ino == ino => return Ok(entry),
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            // The path is gone or refers to another file now
+            self.update_inode_table(|cache| cache.remove_path(ino, &path));
         }
     }
 
+    /// Returns the [FilesystemEntry](filesystem_entry::FilesystemEntry) at `path`.
+    /// The inode table is only locked for writing if the entry is new to it.
     pub fn get_entry(
-        &mut self,
-        path: &str,
+        &self,
+        path: &Path,
     ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
-        println!("Getting entry {}", path);
-        let rpath = self.join_root_path(path);
-        if rpath.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
-        }
-        let rpath = rpath.unwrap();
-        let m = fs::metadata(&rpath);
+        let (mut ret, m) = self.stat_entry(path)?;
+        let btime = filesystem_entry::creation_time(&m);
+        let known = self
+            .inode_cache
+            .read()
+            .unwrap()
+            .get(m.st_dev(), m.st_ino(), btime.as_ref(), path);
+        let (ino, generation) = match known {
+            Some(known) => known,
+            None => self.update_inode_table(|cache| cache.lookup(m.st_dev(), m.st_ino(), btime, path)),
+        };
+        ret.ino = ino;
+        ret.generation = generation;
+        return Ok(ret);
+    }
+
+    /// Returns the [FilesystemEntry](filesystem_entry::FilesystemEntry) at `path` while the inode table is locked
+    fn lookup_entry(
+        &self,
+        cache: &mut InodeCache,
+        path: &Path,
+    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
+        let (mut ret, m) = self.stat_entry(path)?;
+        let btime = filesystem_entry::creation_time(&m);
+        let (ino, generation) = cache.lookup(m.st_dev(), m.st_ino(), btime, path);
+        ret.ino = ino;
+        ret.generation = generation;
+        return Ok(ret);
+    }
+
+    /// Reads the metadata of the entry at `path` without touching the inode table.
+    /// The inode id of the returned entry is not set yet.
+    fn stat_entry(
+        &self,
+        path: &Path,
+    ) -> Result<(filesystem_entry::FilesystemEntry, fs::Metadata), std::io::Error> {
+        println!("Getting entry {}", path.display());
+        let rpath = self.resolve(path)?;
+        let m = rpath.metadata();
         if m.is_err() {
             return Err(m.unwrap_err());
         }
         let m = m.unwrap();
-        let mut ret = filesystem_entry::FilesystemEntry::new_file(
-            String::from(path),
-            m.st_ino(),
-            m.st_size(),
-            m.st_blocks(),
-            755,
-            m.st_gid(),
-            m.st_uid(),
-        );
-        if m.is_dir() {
-            ret = filesystem_entry::FilesystemEntry::new_directory(
-                String::from(path),
-                m.st_ino(),
-                m.st_size(),
-                m.st_blocks(),
-                755,
-                m.st_gid(),
-                m.st_uid(),
-            );
+        if !self.cross_mounts && !self.root_dir.same_mount(&rpath, &m)? {
+            // The entry is a mount point or below one
+            return Err(std::io::Error::from_raw_os_error(libc::EXDEV));
         }
-        self.inode_cache.insert(m.st_ino(), ret.clone());
-        return Ok(ret);
+        let ret = filesystem_entry::FilesystemEntry::from_metadata(path.to_path_buf(), &m);
+        return Ok((ret, m));
     }
 
+    /// Reads up to `size` bytes at `offset`.
+    /// Fewer bytes are returned only if the end of the file was reached.
     pub fn read(&self, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, std::io::Error> {
+        if offset < 0 {
+            return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let fh = self.resolve_inode(ino)?.open(libc::O_RDONLY, 0)?;
+        return FilesystemAPI::read_file(&fh, offset, size);
+    }
+
+    /// Reads up to `size` bytes at `offset` from an open file.
+    /// Fewer bytes are returned only if the end of the file was reached.
+    pub fn read_file(fh: &fs::File, offset: i64, size: u32) -> Result<Vec<u8>, std::io::Error> {
+        if offset < 0 {
+            return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+        }
         let mut chunk = vec![0; size as usize];
-        let file = self.get_entry_from_inode(ino).unwrap();
-
-        let path = self.join_root_path(&file.path);
-        if path.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
+        let mut filled = 0;
+        while filled < chunk.len() {
+            match fh.read_at(&mut chunk[filled..], offset as u64 + filled as u64) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
         }
-        let path = path.unwrap();
-        let mut fh = std::fs::File::open(&path).expect("File Opening failed");
-
-        let _ = fh.seek(SeekFrom::Start(offset as u64));
-        let _ = fh.read_exact(&mut chunk);
-        /*
-        unwrap_or_err(fh.seek(SeekFrom::Start(offset as u64)), "Error while seeking file");
-        fh.read_exact(&mut chunk).expect("File could not be read");
-*/
+        chunk.truncate(filled);
         return Ok(chunk);
     }
 
+    /// Renames an entry, replacing the entry at the new path if there is one.
+    /// The inode table is locked throughout, so the paths of the inode and its descendants never go stale.
     pub fn rename(
-        &mut self,
+        &self,
         parent: u64,
-        name: &str,
+        name: &OsStr,
         nparent: u64,
-        nname: &str,
+        nname: &OsStr,
     ) -> Result<(), std::io::Error> {
-        let parent_path = self.get_path_from_inode(parent).unwrap();
-        let file_path = Path::new(&parent_path).join(name);
+        let file_path = &self.child_path(parent, name)?;
+        let nfile_path = &self.child_path(nparent, nname)?;
 
-        let nparent_path = self.get_path_from_inode(nparent).unwrap();
-        let nfile_path = Path::new(&nparent_path).join(nname);
+        let rpath = self.resolve(file_path)?;
+        let nrpath = self.resolve(nfile_path)?;
 
-        let err = self.get_entry(nfile_path.to_str().unwrap());
-        if err.is_err() {
-            let err = err.unwrap_err();
-            if err.kind() == std::io::ErrorKind::AlreadyExists {
-                return Err(err);
+        return self.update_inode_table(|cache| {
+            let source = self.lookup_entry(cache, file_path)?;
+            let target = self.lookup_entry(cache, nfile_path);
+            if target.is_ok() && target.unwrap().ino == source.ino {
+                // Both are links to the same inode, rename does nothing
+                return Ok(());
             }
-        }
-
-        let rpath = self.join_root_path(file_path.to_str().unwrap());
-        let nrpath = self.join_root_path(nfile_path.to_str().unwrap());
-
-        if rpath.is_err() || nrpath.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
-        }
-        let rpath = rpath.unwrap();
-        let nrpath = nrpath.unwrap();
-
-        println!("rename {} -> {}", &rpath, &nrpath);
-        let err = std::fs::rename(rpath, nrpath);
-        if err.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
-        } else {
+            rpath.rename(&nrpath)?;
+            cache.rename(file_path, nfile_path);
             return Ok(());
-        }
+        });
     }
 
+    /// Creates a directory with the permission bits of `mode`, limited by the umask of the server
     pub fn mkdir(
-        &mut self,
+        &self,
         parent: u64,
-        name: &str,
+        name: &OsStr,
+        mode: u32,
     ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
-        let parent_path = self.get_path_from_inode(parent).unwrap();
-        let file_path = Path::new(&parent_path).join(name);
-        let rpath = self.join_root_path(&file_path.to_str().expect(""));
-        if rpath.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
-        }
-        let rpath = rpath.unwrap();
-        let err = std::fs::create_dir(rpath);
-        if err.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
-        } else {
-            let ret = self.get_entry(file_path.to_str().expect("")).expect("");
-            return Ok(ret);
+        let file_path = self.child_path(parent, name)?;
+        self.resolve(&file_path)?.mkdir(mode & 0o7777)?;
+        return self.get_entry(&file_path);
+    }
+
+    /// Flushes a file to disk.
+    /// If `datasync` is set only the data and the metadata needed to read it back are flushed.
+    pub fn fsync(&self, ino: u64, datasync: bool) -> Result<(), std::io::Error> {
+        let file = self.resolve_inode(ino)?.open(libc::O_RDONLY, 0)?;
+        return FilesystemAPI::fsync_file(&file, datasync);
+    }
+
+    /// Flushes an open file to disk, see [fsync](FilesystemAPI::fsync)
+    pub fn fsync_file(file: &fs::File, datasync: bool) -> Result<(), std::io::Error> {
+        if datasync {
+            return file.sync_data();
         }
+        return file.sync_all();
     }
 
+    /// Opens a file honouring the access mode and O_APPEND, O_TRUNC, O_SYNC, O_DSYNC and O_NONBLOCK of `flags`
+    pub fn open(&self, ino: u64, flags: i32) -> Result<fs::File, std::io::Error> {
+        return self.resolve_inode(ino)?.open(open_flags(flags), 0);
+    }
+
+    /// Creates and opens a file.
+    /// Fails with EEXIST if `flags` contains O_EXCL and the file exists, otherwise it is opened like [open](FilesystemAPI::open).
     pub fn create(
-        &mut self,
+        &self,
         parent: u64,
-        name: &str,
-    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
-        let parent_path = self.get_path_from_inode(parent).unwrap();
-        let file_path = Path::new(&parent_path).join(name);
-        let rpath = self.join_root_path(&file_path.to_str().expect(""));
-        if rpath.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
-        }
-        let rpath = rpath.unwrap();
-        let err = std::fs::File::create(rpath);
-        if err.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
-        } else {
-            let ret = self.get_entry(file_path.to_str().expect("")).expect("");
-            return Ok(ret);
-        }
+        name: &OsStr,
+        flags: i32,
+        mode: u32,
+    ) -> Result<(filesystem_entry::FilesystemEntry, fs::File), std::io::Error> {
+        let file_path = &self.child_path(parent, name)?;
+        let rpath = self.resolve(file_path)?;
+        let file = rpath.open(open_flags(flags) | libc::O_CREAT | (flags & libc::O_EXCL), mode & 0o7777)?;
+        let entry = self.get_entry(file_path)?;
+        return Ok((entry, file));
     }
 
+    /// Writes `data` at `offset` into the file, extending it if necessary.
+    /// Returns the number of bytes written, which is less than `data.len()` on a partial write.
     pub fn write(&self, ino: u64, offset: i64, data: &[u8]) -> Result<u32, std::io::Error> {
-        let file_path = self.get_path_from_inode(ino).unwrap();
-        let rpath = self.join_root_path(&file_path);
-        if rpath.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
+        if offset < 0 {
+            return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let file = self.resolve_inode(ino)?.open(libc::O_WRONLY, 0)?;
+        return FilesystemAPI::write_file(&file, offset, data);
+    }
+
+    /// Writes `data` at `offset` into an open file.
+    /// Files opened with O_APPEND are always appended to.
+    /// Returns the number of bytes written, which is less than `data.len()` on a partial write.
+    pub fn write_file(file: &fs::File, offset: i64, data: &[u8]) -> Result<u32, std::io::Error> {
+        if offset < 0 {
+            return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
         }
-        let rpath = rpath.unwrap();
-        let mut file = std::fs::File::open(&rpath).unwrap();
-        unwrap_or_err(file.seek(SeekFrom::Start(offset as u64)), "Error while seeking file");
-        let written = std::fs::write(&rpath, data);
-        // TODO : Fix Writes
-        //let written = file.write(data);
-        if written.is_err() {
-            let err = written.unwrap_err();
-            return Err(err);
-        } else {
-            return Ok(0);
+        let mut written = 0;
+        while written < data.len() {
+            match file.write_at(&data[written..], offset as u64 + written as u64) {
+                Ok(0) => break,
+                Ok(n) => written += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    if written == 0 {
+                        return Err(e);
+                    }
+                    // Reporting what made it to disk before the error
+                    break;
+                }
+            }
         }
+        return Ok(written as u32);
     }
 
-    pub fn unlink(&self, parent: u64, name: &str) -> Result<(), std::io::Error> {
-        let parent_path = self.get_path_from_inode(parent).unwrap();
-        let file_path = Path::new(&parent_path).join(name);
-        let rpath = self.join_root_path(&file_path.to_str().expect(""));
-        if rpath.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
+    pub fn setattr(
+        &self,
+        ino: u64,
+        changes: &filesystem_entry::AttrChanges,
+    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
+        let file_path = self.get_path_from_inode(ino)?;
+        let location = self.resolve(&file_path)?;
+
+        // Ownership first, as changing it may clear the setuid and setgid bits
+        if changes.uid.is_some() || changes.gid.is_some() {
+            location.set_owner(changes.uid, changes.gid)?;
         }
-        let rpath = rpath.unwrap();
-        let err = std::fs::remove_file(rpath);
-        if err.is_err() {
-            return err;
-        } else {
-            return Ok(());
+        if changes.mode.is_some() {
+            location.set_mode(changes.mode.unwrap() & 0o7777)?;
+        }
+        if changes.size.is_some() {
+            let file = location.open(libc::O_WRONLY, 0)?;
+            file.set_len(changes.size.unwrap())?;
+        }
+        if changes.atime.is_some() || changes.mtime.is_some() {
+            location.set_times(changes.atime.as_ref(), changes.mtime.as_ref())?;
+        }
+
+        return self.get_entry(&file_path);
+    }
+
+    /// Returns the capacity of the filesystem the Volume is stored on
+    pub fn statfs(&self) -> Result<filesystem_entry::FsStats, std::io::Error> {
+        let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+        self.resolve(Path::new("/"))?.statvfs(&mut st)?;
+        return Ok(filesystem_entry::FsStats {
+            blocks: st.f_blocks as u64,
+            bfree: st.f_bfree as u64,
+            bavail: st.f_bavail as u64,
+            files: st.f_files as u64,
+            ffree: st.f_ffree as u64,
+            bsize: st.f_bsize as u32,
+            namelen: st.f_namemax as u32,
+            frsize: st.f_frsize as u32,
+        });
+    }
+
+    /// Returns the bytes allocated by the entries of the volume.
+    /// Hard linked files are counted once, filesystems mounted inside the volume are not counted.
+    /// Entries which can not be read are skipped.
+    pub fn usage(&self) -> Result<u64, std::io::Error> {
+        let mut seen = HashSet::new();
+        let mut used = 0;
+        let mut dirs = vec![self.root_dir.resolve_dir(Path::new("/"))?];
+        while let Some(dir) = dirs.pop() {
+            let entries = dir.read_dir();
+            if entries.is_err() {
+                continue;
+            }
+            for entry in entries.unwrap() {
+                // Not following symlinks
+                let m = entry.metadata();
+                if m.is_err() {
+                    continue;
+                }
+                let m = m.unwrap();
+                if m.st_dev() != self.root_dev {
+                    continue;
+                }
+                if m.st_nlink() > 1 && !seen.insert((m.st_dev(), m.st_ino())) {
+                    continue;
+                }
+                used += m.st_blocks() * 512;
+                if m.is_dir() {
+                    dirs.push(entry);
+                }
+            }
         }
+        return Ok(used);
     }
 
-    pub fn rmdir(&self, parent: u64, name: &str) -> Result<(), std::io::Error> {
-        let parent_path = self.get_path_from_inode(parent).unwrap();
-        let file_path = Path::new(&parent_path).join(name);
-        let rpath = self.join_root_path(&file_path.to_str().expect(""));
-        if rpath.is_err() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::PermissionDenied,
-                "",
-            ));
+    /// Returns the [usage](FilesystemAPI::usage) of the volume, walking it at most once every [USAGE_TTL].
+    /// Concurrent callers wait for the walk in progress instead of walking the volume as well.
+    pub fn cached_usage(&self) -> Result<u64, std::io::Error> {
+        let mut cached = self.usage.lock().unwrap();
+        if let Some((walked, used)) = *cached {
+            if walked.elapsed() < USAGE_TTL {
+                return Ok(used);
+            }
         }
-        let rpath = rpath.unwrap();
-        println!("trying to remove {}", rpath);
-        return std::fs::remove_dir(rpath);
+        let used = self.usage()?;
+        *cached = Some((Instant::now(), used));
+        return Ok(used);
+    }
+
+    /// Creates a special file such as a FIFO, socket or device node.
+    /// `mode` contains both file type and permissions, `rdev` the device ID of device nodes.
+    pub fn mknod(
+        &self,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        rdev: u32,
+    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
+        let file_path = self.child_path(parent, name)?;
+        self.resolve(&file_path)?.mknod(mode, rdev)?;
+        return self.get_entry(&file_path);
+    }
+
+    /// Returns the value of an Extended Attribute
+    pub fn getxattr(&self, ino: u64, name: &OsStr) -> Result<Vec<u8>, std::io::Error> {
+        return xattr::get(&self.resolve_inode(ino)?.xattr_path()?, name.as_bytes());
+    }
+
+    /// Returns the names of all Extended Attributes
+    pub fn listxattr(&self, ino: u64) -> Result<Vec<Vec<u8>>, std::io::Error> {
+        return xattr::list(&self.resolve_inode(ino)?.xattr_path()?);
+    }
+
+    /// Sets an Extended Attribute, `flags` are the flags of setxattr(2)
+    pub fn setxattr(
+        &self,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+    ) -> Result<(), std::io::Error> {
+        return xattr::set(&self.resolve_inode(ino)?.xattr_path()?, name.as_bytes(), value, flags);
+    }
+
+    /// Removes an Extended Attribute
+    pub fn removexattr(&self, ino: u64, name: &OsStr) -> Result<(), std::io::Error> {
+        return xattr::remove(&self.resolve_inode(ino)?.xattr_path()?, name.as_bytes());
+    }
+
+    /// Creates a hard link `nname` in `nparent` to the file `ino`
+    pub fn link(
+        &self,
+        ino: u64,
+        nparent: u64,
+        nname: &OsStr,
+    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
+        let file_path = self.get_path_from_inode(ino)?;
+        let nfile_path = self.child_path(nparent, nname)?;
+
+        self.resolve(&file_path)?.hard_link(&self.resolve(&nfile_path)?)?;
+        return self.get_entry(&nfile_path);
     }
+
+    /// Creates a symlink `name` in `parent` pointing to `target`.
+    /// The target is stored verbatim and never resolved on the server.
+    pub fn symlink(
+        &self,
+        parent: u64,
+        name: &OsStr,
+        target: &Path,
+    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
+        let file_path = self.child_path(parent, name)?;
+        self.resolve(&file_path)?.symlink(target)?;
+        return self.get_entry(&file_path);
+    }
+
+    /// Returns the target of a symlink
+    pub fn readlink(&self, ino: u64) -> Result<PathBuf, std::io::Error> {
+        return self.resolve_inode(ino)?.read_link();
+    }
+
+    /// Removes a file, forgetting the path in the inode table
+    pub fn unlink(&self, parent: u64, name: &OsStr) -> Result<(), std::io::Error> {
+        let file_path = &self.child_path(parent, name)?;
+        let rpath = self.resolve(file_path)?;
+        return self.update_inode_table(|cache| {
+            let ino = self.lookup_entry(cache, file_path)?.ino;
+            rpath.remove_file()?;
+            cache.remove_path(ino, file_path);
+            return Ok(());
+        });
+    }
+
+    /// Removes an empty directory, forgetting it and every path below it in the inode table
+    pub fn rmdir(&self, parent: u64, name: &OsStr) -> Result<(), std::io::Error> {
+        let file_path = &self.child_path(parent, name)?;
+        let rpath = self.resolve(file_path)?;
+        return self.update_inode_table(|cache| {
+            self.lookup_entry(cache, file_path)?;
+            rpath.remove_dir()?;
+            cache.remove_tree(file_path);
+            return Ok(());
+        });
+    }
+}
+
+/// Returns the flags to open a file with for the `flags` of an open call
+fn open_flags(flags: i32) -> i32 {
+    let access = match flags & libc::O_ACCMODE {
+        libc::O_RDONLY => libc::O_RDONLY,
+        libc::O_WRONLY => libc::O_WRONLY,
+        _ => libc::O_RDWR,
+    };
+    return access | (flags & (libc::O_APPEND | libc::O_TRUNC | libc::O_SYNC | libc::O_DSYNC | libc::O_NONBLOCK));
 }
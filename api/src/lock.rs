@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+/// Kind of a POSIX advisory lock
+pub enum LockKind {
+    /// Shared lock, any number of owners can hold one on the same range
+    Read,
+    /// Exclusive lock
+    Write,
+    /// Releases a range, or no conflicting lock when returned by getlk
+    Unlock,
+}
+
+impl LockKind {
+    /// Converts a `F_RDLCK`, `F_WRLCK` or `F_UNLCK` lock type of fcntl
+    pub fn from_fcntl(typ: i32) -> Option<LockKind> {
+        return match typ {
+            libc::F_RDLCK => Some(LockKind::Read),
+            libc::F_WRLCK => Some(LockKind::Write),
+            libc::F_UNLCK => Some(LockKind::Unlock),
+            _ => None,
+        };
+    }
+
+    /// Returns the fcntl lock type of the [LockKind]
+    pub fn to_fcntl(&self) -> i32 {
+        return match self {
+            LockKind::Read => libc::F_RDLCK,
+            LockKind::Write => libc::F_WRLCK,
+            LockKind::Unlock => libc::F_UNLCK,
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Byte-range lock on a file
+pub struct FileLock {
+    /// First byte of the range
+    pub start: u64,
+    /// Last byte of the range, inclusive
+    pub end: u64,
+    /// Kind of the lock
+    pub kind: LockKind,
+    /// PID of the process holding the lock on its client
+    pub pid: u32,
+}
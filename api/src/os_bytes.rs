@@ -0,0 +1,43 @@
+//! Serialization of paths and names as raw bytes, as names on the server need not be valid UTF-8.
+//!
+//! Used with `#[serde(with = "crate::os_bytes")]` on [PathBuf](std::path::PathBuf) and [OsString] fields.
+//! Strings are accepted as well, so the inode tables of earlier versions can still be read.
+
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_bytes::ByteBuf;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+pub fn serialize<T: AsRef<OsStr>, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    return serializer.serialize_bytes(value.as_ref().as_bytes());
+}
+
+pub fn deserialize<'de, T: From<OsString>, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+    let bytes = ByteBuf::deserialize(deserializer)?;
+    return Ok(T::from(OsString::from_vec(bytes.into_vec())));
+}
+
+/// Same for lists of paths or names
+pub mod list {
+    use serde::ser::SerializeSeq;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde_bytes::{ByteBuf, Bytes};
+    use std::ffi::{OsStr, OsString};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    pub fn serialize<T: AsRef<OsStr>, S: Serializer>(values: &[T], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(values.len()))?;
+        for value in values {
+            seq.serialize_element(Bytes::new(value.as_ref().as_bytes()))?;
+        }
+        return seq.end();
+    }
+
+    pub fn deserialize<'de, T: From<OsString>, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<T>, D::Error> {
+        let list = Vec::<ByteBuf>::deserialize(deserializer)?;
+        return Ok(list
+            .into_iter()
+            .map(|bytes| T::from(OsString::from_vec(bytes.into_vec())))
+            .collect());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::encoding::Encoding;
+use serde::{Deserialize, Serialize};
+
+/// Version of the wire protocol.
+/// Bumped on every incompatible change of [Call](crate::calls::Call) or [Response](crate::responses::Response).
+pub const PROTOCOL_VERSION: u32 = 9;
+
+/// zstd compression of [Response](crate::responses::Response)s
+pub const COMPRESSION_ZSTD: &str = "zstd";
+
+/// No authentication
+pub const AUTH_NONE: &str = "none";
+
+/// Extended Attribute calls
+pub const CALL_XATTR: &str = "xattr";
+
+/// POSIX lock calls
+pub const CALL_LOCKS: &str = "locks";
+
+/// Directory listings with attributes
+pub const CALL_READDIRPLUS: &str = "readdirplus";
+
+/// Maximum size of the data of a single read or write (16 MiB)
+pub const MAX_IO_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Maximum length of a path or symlink target in bytes
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// Maximum length of a file name in bytes
+pub const MAX_NAME_LEN: usize = 255;
+
+/// Maximum length of the name of an Extended Attribute in bytes
+pub const MAX_XATTR_NAME_LEN: usize = 255;
+
+/// Maximum size of the value of an Extended Attribute (64 KiB)
+pub const MAX_XATTR_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+/// Capabilities of one side of a connection exchanged during the handshake
+pub struct Capabilities {
+    /// Supported compression algorithms
+    pub compression: Vec<String>,
+    /// Supported wire encodings in order of preference
+    pub encodings: Vec<Encoding>,
+    /// Supported authentication methods
+    pub auth: Vec<String>,
+    /// Supported optional calls
+    pub calls: Vec<String>,
+}
+
+impl Capabilities {
+    /// Returns every [Capabilities] supported by this build
+    pub fn supported() -> Capabilities {
+        return Capabilities {
+            compression: vec![COMPRESSION_ZSTD.to_string()],
+            encodings: vec![Encoding::Cbor, Encoding::Json],
+            auth: vec![AUTH_NONE.to_string()],
+            calls: vec![
+                CALL_XATTR.to_string(),
+                CALL_LOCKS.to_string(),
+                CALL_READDIRPLUS.to_string(),
+            ],
+        };
+    }
+
+    /// Negotiates the [Capabilities] used by a connection.
+    ///
+    /// `self` are the capabilities of the server, `requested` the ones of the client.
+    /// The result contains exactly one encoding, the first of the client supported by the server.
+    /// Returns an error message if no common encoding or authentication method exists.
+    pub fn negotiate(&self, requested: &Capabilities) -> Result<Capabilities, String> {
+        let encoding = requested
+            .encodings
+            .iter()
+            .find(|e| self.encodings.contains(e));
+        if encoding.is_none() {
+            return Err(String::from("No common encoding"));
+        }
+        let auth = intersect(&self.auth, &requested.auth);
+        if auth.is_empty() {
+            return Err(String::from("No common authentication method"));
+        }
+        return Ok(Capabilities {
+            compression: intersect(&self.compression, &requested.compression),
+            encodings: vec![*encoding.unwrap()],
+            auth,
+            calls: intersect(&self.calls, &requested.calls),
+        });
+    }
+
+    /// Returns the negotiated [Encoding]
+    pub fn encoding(&self) -> Encoding {
+        return self.encodings.first().copied().unwrap_or_default();
+    }
+
+    /// Returns true if `compression` may be used
+    pub fn has_compression(&self, compression: &str) -> bool {
+        return self.compression.iter().any(|c| c == compression);
+    }
+
+    /// Returns true if the optional `call` may be used
+    pub fn has_call(&self, call: &str) -> bool {
+        return self.calls.iter().any(|c| c == call);
+    }
+}
+
+/// Returns the elements of `requested` also contained in `supported`
+fn intersect(supported: &[String], requested: &[String]) -> Vec<String> {
+    return requested
+        .iter()
+        .filter(|e| supported.contains(e))
+        .cloned()
+        .collect();
+}
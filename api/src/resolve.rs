@@ -0,0 +1,509 @@
+//! Resolution of client paths beneath the root directory of a Volume.
+//!
+//! The root is held open and every directory leading to an entry is opened relative to it,
+//! with `openat2(RESOLVE_BENEATH | RESOLVE_NO_MAGICLINKS)` where the kernel supports it.
+//! Otherwise the directories are opened one component at a time refusing any symlink.
+//! The last component is never resolved, callers must not follow it.
+//! Entries are accessed by their name in the open parent directory with the `*at` family of calls,
+//! so paths are never looked up again from the root.
+//!
+//! If crossing mounts is not allowed `RESOLVE_NO_XDEV` is added, or every directory of the walk
+//! is checked to be on the mount of the root. Mount ids tell apart bind mounts of the same filesystem.
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::{self, File};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::filesystem_entry::FsTimespec;
+
+/// Flags of the directory handles
+#[cfg(target_os = "linux")]
+const O_DIR_HANDLE: i32 = libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC;
+#[cfg(not(target_os = "linux"))]
+const O_DIR_HANDLE: i32 = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+
+/// Flags of the handles the metadata of entries is read from, symlinks are opened themselves
+#[cfg(target_os = "linux")]
+const O_ENTRY_HANDLE: i32 = libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+#[cfg(not(target_os = "linux"))]
+const O_ENTRY_HANDLE: i32 = libc::O_EVTONLY | libc::O_SYMLINK | libc::O_NONBLOCK | libc::O_CLOEXEC;
+
+/// Cleared once openat2 turned out to be unavailable, e.g. on kernels before 5.6 or under seccomp
+#[cfg(target_os = "linux")]
+static OPENAT2: AtomicBool = AtomicBool::new(true);
+
+/// The open root directory of a Volume
+pub(crate) struct RootDir {
+    dir: Arc<File>,
+    /// Device of the root
+    dev: u64,
+    /// Mount id of the root, if the kernel reports mount ids
+    mnt_id: Option<u64>,
+    /// If directories on other mounts inside the root can be resolved
+    cross_mounts: bool,
+}
+
+/// An entry inside the Volume, addressed by its name in an open parent directory
+pub(crate) struct Location {
+    dir: Arc<File>,
+    name: OsString,
+}
+
+impl Location {
+    fn new(dir: Arc<File>, name: &OsStr) -> Location {
+        return Location {
+            dir,
+            name: name.to_os_string(),
+        };
+    }
+
+    /// Returns the name of the entry in its parent directory
+    pub(crate) fn name(&self) -> &OsStr {
+        return &self.name;
+    }
+
+    fn cname(&self) -> Result<CString, std::io::Error> {
+        return Ok(CString::new(self.name.as_bytes())?);
+    }
+
+    /// Opens the entry with the flags of open(2), never following a symlink
+    pub(crate) fn open(&self, flags: i32, mode: u32) -> Result<File, std::io::Error> {
+        return self.open_raw(flags | libc::O_NOFOLLOW | libc::O_CLOEXEC, mode);
+    }
+
+    fn open_raw(&self, flags: i32, mode: u32) -> Result<File, std::io::Error> {
+        let name = self.cname()?;
+        let fd = unsafe { libc::openat(self.dir.as_raw_fd(), name.as_ptr(), flags, mode as libc::c_uint) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        return Ok(unsafe { File::from_raw_fd(fd) });
+    }
+
+    /// Returns the metadata of the entry, not following symlinks
+    pub(crate) fn metadata(&self) -> Result<fs::Metadata, std::io::Error> {
+        return self.open_raw(O_ENTRY_HANDLE, 0)?.metadata();
+    }
+
+    /// Returns the mount id of the entry, or None if the kernel does not report mount ids
+    fn mount_id(&self) -> Result<Option<u64>, std::io::Error> {
+        return mount_id(self.dir.as_raw_fd(), Some(&self.name));
+    }
+
+    /// Returns the entries of the directory, without `.` and `..`.
+    /// They are addressed inside the directory opened here, wherever it is moved to meanwhile.
+    pub(crate) fn read_dir(&self) -> Result<Vec<Location>, std::io::Error> {
+        let dir = Arc::new(self.open(libc::O_RDONLY | libc::O_DIRECTORY, 0)?);
+        let names = read_dir(dir.try_clone()?)?;
+        return Ok(names.iter().map(|name| Location::new(dir.clone(), name)).collect());
+    }
+
+    /// Reads the capacity of the filesystem the entry is stored on into `st`
+    pub(crate) fn statvfs(&self, st: &mut libc::statvfs) -> Result<(), std::io::Error> {
+        let file = self.open(libc::O_RDONLY | libc::O_NONBLOCK, 0)?;
+        return check(unsafe { libc::fstatvfs(file.as_raw_fd(), st) });
+    }
+
+    /// Creates a directory with the permission bits of `mode`, limited by the umask
+    pub(crate) fn mkdir(&self, mode: u32) -> Result<(), std::io::Error> {
+        let name = self.cname()?;
+        return check(unsafe { libc::mkdirat(self.dir.as_raw_fd(), name.as_ptr(), mode as libc::mode_t) });
+    }
+
+    /// Creates a special file, see [mknod](crate::FilesystemAPI::mknod)
+    pub(crate) fn mknod(&self, mode: u32, rdev: u32) -> Result<(), std::io::Error> {
+        let name = self.cname()?;
+        return check(unsafe {
+            libc::mknodat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                mode as libc::mode_t,
+                rdev as libc::dev_t,
+            )
+        });
+    }
+
+    /// Creates a symlink pointing to `target`
+    pub(crate) fn symlink(&self, target: &Path) -> Result<(), std::io::Error> {
+        let name = self.cname()?;
+        let ctarget = CString::new(target.as_os_str().as_bytes())?;
+        return check(unsafe { libc::symlinkat(ctarget.as_ptr(), self.dir.as_raw_fd(), name.as_ptr()) });
+    }
+
+    /// Creates a hard link at `new` to the entry, a symlink is linked itself
+    pub(crate) fn hard_link(&self, new: &Location) -> Result<(), std::io::Error> {
+        let (name, nname) = (self.cname()?, new.cname()?);
+        return check(unsafe {
+            libc::linkat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                new.dir.as_raw_fd(),
+                nname.as_ptr(),
+                0,
+            )
+        });
+    }
+
+    /// Renames the entry to `new`, replacing the entry there if there is one
+    pub(crate) fn rename(&self, new: &Location) -> Result<(), std::io::Error> {
+        let (name, nname) = (self.cname()?, new.cname()?);
+        return check(unsafe {
+            libc::renameat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                new.dir.as_raw_fd(),
+                nname.as_ptr(),
+            )
+        });
+    }
+
+    /// Removes the entry, which must not be a directory
+    pub(crate) fn remove_file(&self) -> Result<(), std::io::Error> {
+        let name = self.cname()?;
+        return check(unsafe { libc::unlinkat(self.dir.as_raw_fd(), name.as_ptr(), 0) });
+    }
+
+    /// Removes the entry, which must be an empty directory
+    pub(crate) fn remove_dir(&self) -> Result<(), std::io::Error> {
+        let name = self.cname()?;
+        return check(unsafe { libc::unlinkat(self.dir.as_raw_fd(), name.as_ptr(), libc::AT_REMOVEDIR) });
+    }
+
+    /// Returns the target of the symlink
+    pub(crate) fn read_link(&self) -> Result<PathBuf, std::io::Error> {
+        let name = self.cname()?;
+        let mut buf = vec![0u8; 256];
+        loop {
+            let len = unsafe {
+                libc::readlinkat(
+                    self.dir.as_raw_fd(),
+                    name.as_ptr(),
+                    buf.as_mut_ptr() as *mut libc::c_char,
+                    buf.len(),
+                )
+            };
+            if len < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            if (len as usize) < buf.len() {
+                buf.truncate(len as usize);
+                return Ok(PathBuf::from(OsString::from_vec(buf)));
+            }
+            // The target may have been truncated
+            buf.resize(buf.len() * 2, 0);
+        }
+    }
+
+    /// Changes owner and group of the entry, `None` leaves them unchanged
+    pub(crate) fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> Result<(), std::io::Error> {
+        let name = self.cname()?;
+        // -1 leaves the id unchanged
+        let uid = uid.map_or(libc::uid_t::MAX, |uid| uid as libc::uid_t);
+        let gid = gid.map_or(libc::gid_t::MAX, |gid| gid as libc::gid_t);
+        return check(unsafe {
+            libc::fchownat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                uid,
+                gid,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        });
+    }
+
+    /// Sets the permission bits of the entry.
+    /// Permissions of symlinks can not be changed on Linux, which fails with EOPNOTSUPP.
+    pub(crate) fn set_mode(&self, mode: u32) -> Result<(), std::io::Error> {
+        let name = self.cname()?;
+        return check(unsafe {
+            libc::fchmodat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                mode as libc::mode_t,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        });
+    }
+
+    /// Sets access and modification time of the entry without following symlinks.
+    /// `None` leaves the time unchanged.
+    pub(crate) fn set_times(&self, atime: Option<&FsTimespec>, mtime: Option<&FsTimespec>) -> Result<(), std::io::Error> {
+        let to_timespec = |t: Option<&FsTimespec>| match t {
+            Some(t) => libc::timespec {
+                tv_sec: t.sec as libc::time_t,
+                tv_nsec: t.nsec as libc::c_long,
+            },
+            None => libc::timespec {
+                tv_sec: 0,
+                tv_nsec: libc::UTIME_OMIT,
+            },
+        };
+        let times = [to_timespec(atime), to_timespec(mtime)];
+        let name = self.cname()?;
+        return check(unsafe {
+            libc::utimensat(
+                self.dir.as_raw_fd(),
+                name.as_ptr(),
+                times.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        });
+    }
+
+    /// Returns a path to the entry for the Extended Attribute calls, which have no `*at` variant.
+    ///
+    /// The path goes through `/proc/self/fd` of the parent directory,
+    /// so only the last component is looked up by the call.
+    /// Without it Extended Attributes are not supported and EOPNOTSUPP is returned.
+    pub(crate) fn xattr_path(&self) -> Result<PathBuf, std::io::Error> {
+        let fds = Path::new("/proc/self/fd");
+        if !fds.is_dir() {
+            return Err(std::io::Error::from_raw_os_error(libc::EOPNOTSUPP));
+        }
+        return Ok(fds.join(self.dir.as_raw_fd().to_string()).join(&self.name));
+    }
+}
+
+/// Turns the return value of a libc call into a Result
+fn check(ret: i32) -> Result<(), std::io::Error> {
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    return Ok(());
+}
+
+/// Returns the names of the entries of the open directory `dir`, without `.` and `..`
+fn read_dir(dir: File) -> Result<Vec<OsString>, std::io::Error> {
+    let fd = dir.into_raw_fd();
+    let stream = unsafe { libc::fdopendir(fd) };
+    if stream.is_null() {
+        let err = std::io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    let mut names = vec![];
+    let ret = loop {
+        // The end of the directory is told apart from an error by errno only
+        unsafe { *errno() = 0 };
+        let entry = unsafe { libc::readdir(stream) };
+        if entry.is_null() {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(0) {
+                break Ok(names);
+            }
+            break Err(err);
+        }
+        let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+        if name != b"." && name != b".." {
+            names.push(OsStr::from_bytes(name).to_os_string());
+        }
+    };
+    // Closes the descriptor as well
+    unsafe { libc::closedir(stream) };
+    return ret;
+}
+
+#[cfg(target_os = "linux")]
+fn errno() -> *mut i32 {
+    return unsafe { libc::__errno_location() };
+}
+
+#[cfg(not(target_os = "linux"))]
+fn errno() -> *mut i32 {
+    return unsafe { libc::__error() };
+}
+
+impl RootDir {
+    /// Opens the root directory at `path`
+    pub(crate) fn open(path: &str) -> Result<RootDir, std::io::Error> {
+        let cpath = CString::new(path.as_bytes())?;
+        let fd = unsafe { libc::open(cpath.as_ptr(), O_DIR_HANDLE) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let dir = unsafe { File::from_raw_fd(fd) };
+        return Ok(RootDir {
+            dev: dir.metadata()?.dev(),
+            mnt_id: mount_id(fd, None)?,
+            dir: Arc::new(dir),
+            cross_mounts: true,
+        });
+    }
+
+    /// Sets if directories on other mounts inside the root can be resolved.
+    /// If not, resolving them fails with EXDEV.
+    pub(crate) fn set_cross_mounts(&mut self, cross_mounts: bool) {
+        self.cross_mounts = cross_mounts;
+    }
+
+    /// Returns if the entry at `location` with the metadata `m` is on the mount of the root.
+    /// The device is compared if the kernel does not report mount ids.
+    pub(crate) fn same_mount(&self, location: &Location, m: &fs::Metadata) -> Result<bool, std::io::Error> {
+        if self.mnt_id.is_some() {
+            let id = location.mount_id()?;
+            if id.is_some() {
+                return Ok(id == self.mnt_id);
+            }
+        }
+        return Ok(m.dev() == self.dev);
+    }
+
+    /// Returns the [Location] of `path`, which is relative to the root.
+    /// Fails with EACCES if the path leads outside of the root.
+    pub(crate) fn resolve(&self, path: &Path) -> Result<Location, std::io::Error> {
+        let mut comps = components(path)?;
+        let name = comps.pop();
+        if name.is_none() {
+            return Ok(Location::new(self.dir.clone(), OsStr::new(".")));
+        }
+        let dir = self.open_dir(&comps)?;
+        return Ok(Location::new(Arc::new(dir), name.unwrap()));
+    }
+
+    /// Returns the [Location] of the directory `path` itself, addressed as `.` inside of it.
+    /// In contrast to [resolve](RootDir::resolve) the last component is opened as well.
+    /// Symlinks staying inside the root are only followed where openat2 is available,
+    /// the walk one component at a time refuses any symlink.
+    pub(crate) fn resolve_dir(&self, path: &Path) -> Result<Location, std::io::Error> {
+        let comps = components(path)?;
+        let dir = self.open_dir(&comps)?;
+        return Ok(Location::new(Arc::new(dir), OsStr::new(".")));
+    }
+
+    /// Opens the directory reached by `comps` beneath the root
+    fn open_dir(&self, comps: &[&OsStr]) -> Result<File, std::io::Error> {
+        if comps.is_empty() {
+            return self.dir.try_clone();
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if OPENAT2.load(Ordering::Relaxed) {
+                let dir = self.open_beneath(comps, self.cross_mounts);
+                let errno = dir.as_ref().err().and_then(|e| e.raw_os_error());
+                if errno == Some(libc::EXDEV) {
+                    if !self.cross_mounts && self.open_beneath(comps, true).is_ok() {
+                        // The directory is only refused for being on another mount
+                        return Err(std::io::Error::from_raw_os_error(libc::EXDEV));
+                    }
+                    // A symlink or magic link leads outside of the root
+                    return Err(std::io::Error::from_raw_os_error(libc::EACCES));
+                }
+                if errno != Some(libc::ENOSYS) && errno != Some(libc::EPERM) {
+                    return dir;
+                }
+                OPENAT2.store(false, Ordering::Relaxed);
+            }
+        }
+        return self.open_walk(comps);
+    }
+
+    /// Opens the directory with a single openat2 call confined to the root,
+    /// and to its mount unless `cross_mounts` is set
+    #[cfg(target_os = "linux")]
+    fn open_beneath(&self, comps: &[&OsStr], cross_mounts: bool) -> Result<File, std::io::Error> {
+        let rel: PathBuf = comps.iter().collect();
+        let crel = CString::new(rel.as_os_str().as_bytes())?;
+        let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+        how.flags = O_DIR_HANDLE as u64;
+        how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+        if !cross_mounts {
+            how.resolve |= libc::RESOLVE_NO_XDEV;
+        }
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_openat2,
+                self.dir.as_raw_fd(),
+                crel.as_ptr(),
+                &how as *const libc::open_how,
+                std::mem::size_of::<libc::open_how>(),
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        return Ok(unsafe { File::from_raw_fd(fd as i32) });
+    }
+
+    /// Opens the directory one component at a time, refusing to follow any symlink
+    /// and unless crossing mounts is allowed any directory on another mount
+    fn open_walk(&self, comps: &[&OsStr]) -> Result<File, std::io::Error> {
+        let mut dir = self.dir.as_ref().try_clone()?;
+        for comp in comps {
+            let ccomp = CString::new(comp.as_bytes())?;
+            let fd = unsafe { libc::openat(dir.as_raw_fd(), ccomp.as_ptr(), O_DIR_HANDLE | libc::O_NOFOLLOW) };
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            dir = unsafe { File::from_raw_fd(fd) };
+            if !self.cross_mounts {
+                let id = mount_id(fd, None)?;
+                let same = if id.is_some() && self.mnt_id.is_some() {
+                    id == self.mnt_id
+                } else {
+                    dir.metadata()?.dev() == self.dev
+                };
+                if !same {
+                    return Err(std::io::Error::from_raw_os_error(libc::EXDEV));
+                }
+            }
+        }
+        return Ok(dir);
+    }
+}
+
+/// Returns the mount id of `name` in the directory `dir`, or of `dir` itself without a name.
+/// Symlinks are not followed. None is returned if the kernel does not report mount ids.
+#[cfg(target_os = "linux")]
+fn mount_id(dir: i32, name: Option<&OsStr>) -> Result<Option<u64>, std::io::Error> {
+    let mut flags = libc::AT_SYMLINK_NOFOLLOW | libc::AT_NO_AUTOMOUNT;
+    if name.is_none() {
+        flags |= libc::AT_EMPTY_PATH;
+    }
+    let cname = CString::new(name.unwrap_or_default().as_bytes())?;
+    let mut stx: libc::statx = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::statx(dir, cname.as_ptr(), flags, libc::STATX_MNT_ID, &mut stx) };
+    if ret < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::ENOSYS) {
+            return Ok(None);
+        }
+        return Err(err);
+    }
+    if stx.stx_mask & libc::STATX_MNT_ID == 0 {
+        // Kernels before 5.8
+        return Ok(None);
+    }
+    return Ok(Some(stx.stx_mnt_id));
+}
+
+#[cfg(not(target_os = "linux"))]
+fn mount_id(_dir: i32, _name: Option<&OsStr>) -> Result<Option<u64>, std::io::Error> {
+    return Ok(None);
+}
+
+/// Splits `path` into its normal components, resolving `..` lexically.
+/// Fails with EACCES if `..` would leave the root.
+fn components(path: &Path) -> Result<Vec<&OsStr>, std::io::Error> {
+    if path.as_os_str().as_bytes().contains(&0) {
+        return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    let mut comps = vec![];
+    for comp in path.components() {
+        match comp {
+            Component::Normal(name) => comps.push(name),
+            Component::ParentDir => {
+                if comps.pop().is_none() {
+                    return Err(std::io::Error::from_raw_os_error(libc::EACCES));
+                }
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    return Ok(comps);
+}
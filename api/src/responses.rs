@@ -1,5 +1,9 @@
-use crate::filesystem_entry::FilesystemEntry;
+use crate::error::ShfsError;
+use crate::filesystem_entry::{DirEntry, FilesystemEntry, FsStats};
+use crate::lock::FileLock;
+use crate::protocol::Capabilities;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "kind")]
@@ -12,28 +16,91 @@ pub enum Response {
     /// General Error containg String representation
     Error { error: String },
     #[serde(rename = "io_error")]
-    /// IO Error Response containing the raw os error
-    IOError { error: i32 },
+    /// Failed call
+    IOError { error: ShfsError },
     #[serde(rename = "ok")]
     Ok {},
     // Filesystem Responses
     #[serde(rename = "read_dir")]
-    ReadDir { data: Vec<String> },
+    ReadDir {
+        #[serde(with = "crate::os_bytes::list")]
+        data: Vec<PathBuf>,
+    },
+    #[serde(rename = "read_dir_plus")]
+    /// Entries of a directory with their attributes
+    ReadDirPlus { data: Vec<DirEntry> },
     #[serde(rename = "get_entry")]
     GetEntry { data: FilesystemEntry },
     #[serde(rename = "get_path")]
-    GetPath { data: String },
+    GetPath {
+        #[serde(with = "crate::os_bytes")]
+        data: PathBuf,
+    },
     #[serde(rename = "read")]
-    Read { data: Vec<u8> },
+    /// Data read from a file, `eof` is set if the end of the file was reached
+    Read {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+        eof: bool,
+    },
     #[serde(rename = "write")]
     Write { data: u32 },
+    #[serde(rename = "xattr")]
+    /// Value of an Extended Attribute or list of names
+    Xattr {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    #[serde(rename = "xattr_size")]
+    /// Size of an Extended Attribute or list of names
+    XattrSize { size: u32 },
+    #[serde(rename = "read_link")]
+    /// Target of a symlink
+    ReadLink {
+        #[serde(with = "crate::os_bytes")]
+        data: PathBuf,
+    },
+    #[serde(rename = "open")]
+    /// Handle of an opened file
+    Open { fh: u64 },
+    #[serde(rename = "created")]
+    /// Entry and handle of a created file
+    Created { data: FilesystemEntry, fh: u64 },
+    #[serde(rename = "statfs")]
+    /// Capacity of a volume
+    StatFs { data: FsStats },
+    #[serde(rename = "lock")]
+    /// Lock conflicting with a getlk request
+    Lock { data: FileLock },
     // Server Responses
     #[serde(rename = "list_volumes")]
     ListVolumes { data: Vec<String> },
     #[serde(rename = "volume_lookup")]
     VolumeLookup { id: u64 },
     #[serde(rename = "server_info")]
-    ServerInfo { name: String, version: String },
+    ServerInfo {
+        name: String,
+        version: String,
+        protocol: u32,
+    },
+    #[serde(rename = "handshake")]
+    /// Accepted handshake containing the negotiated [Capabilities]
+    Handshake {
+        version: u32,
+        capabilities: Capabilities,
+    },
     #[serde(rename = "compressed")]
-    Compressed { data: Vec<u8> },
+    Compressed {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+/// A [Response] tagged with the ID of the [Request](crate::calls::Request) it answers
+pub struct Reply {
+    /// ID of the answered request
+    pub id: u64,
+    /// The actual response
+    pub response: Response,
 }
@@ -15,7 +15,8 @@ pub struct Volume {
 }
 
 impl Volume {
-    pub fn new(conf: &VolumeConfig) -> Volume {
+    /// Creates a [Volume], storing its inode table in `state_dir` if set
+    pub fn new(conf: &VolumeConfig, state_dir: Option<&String>) -> Volume {
         let root = conf.root.to_string();
         let name: String;
         if conf.name.is_none() {
@@ -23,11 +24,19 @@ impl Volume {
         } else {
             name = conf.name.clone().unwrap();
         }
+        let mut api = match state_dir {
+            Some(dir) => {
+                let state = Path::new(dir).join(format!("{}.inodes", name));
+                FilesystemAPI::with_state(root.to_string(), state.to_str().unwrap())
+            }
+            None => FilesystemAPI::new(root.to_string()),
+        };
+        api.set_cross_mounts(conf.cross_mounts.unwrap_or(true));
         return Volume {
             name,
             root: root.clone(),
             config: conf.clone(),
-            api: FilesystemAPI::new(root.to_string()),
+            api,
         };
     }
 }
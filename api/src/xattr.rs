@@ -0,0 +1,152 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Extended Attribute data answering a size probe or a read
+#[derive(Debug, Clone, PartialEq)]
+pub enum XattrReply {
+    /// Size of the data if the caller probed with a size of 0
+    Size(u32),
+    /// The data itself
+    Data(Vec<u8>),
+}
+
+impl XattrReply {
+    /// Applies the size probe protocol of getxattr and listxattr.
+    /// A `size` of 0 asks for the size only, a `size` too small for `data` fails with ERANGE.
+    pub fn for_size(data: Vec<u8>, size: u32) -> Result<XattrReply, std::io::Error> {
+        if size == 0 {
+            return Ok(XattrReply::Size(data.len() as u32));
+        }
+        if data.len() > size as usize {
+            return Err(std::io::Error::from_raw_os_error(libc::ERANGE));
+        }
+        return Ok(XattrReply::Data(data));
+    }
+}
+
+/// Returns true if `name` is inside one of `namespaces`, e.g. `user.tag` is inside `user`
+pub fn in_namespaces(name: &[u8], namespaces: &[String]) -> bool {
+    return namespaces.iter().any(|ns| {
+        name.len() > ns.len() && name.starts_with(ns.as_bytes()) && name[ns.len()] == b'.'
+    });
+}
+
+/// Runs a size returning xattr call with a growing buffer until the value fits
+fn read_sized<F: Fn(*mut u8, usize) -> isize>(f: F) -> Result<Vec<u8>, std::io::Error> {
+    loop {
+        let size = f(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut buf = vec![0; size as usize];
+        let read = f(buf.as_mut_ptr(), buf.len());
+        if read < 0 {
+            let err = std::io::Error::last_os_error();
+            // The value grew in between, try again
+            if err.raw_os_error() == Some(libc::ERANGE) {
+                continue;
+            }
+            return Err(err);
+        }
+        buf.truncate(read as usize);
+        return Ok(buf);
+    }
+}
+
+fn cstring(s: &[u8]) -> Result<CString, std::io::Error> {
+    let ret = CString::new(s);
+    if ret.is_err() {
+        return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    return Ok(ret.unwrap());
+}
+
+/// Returns the value of the Extended Attribute `name` of `path`, not following symlinks
+pub fn get(path: &Path, name: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let cpath = cstring(path.as_os_str().as_bytes())?;
+    let cname = cstring(name)?;
+    return read_sized(|buf, size| unsafe {
+        #[cfg(target_os = "linux")]
+        let ret = libc::lgetxattr(cpath.as_ptr(), cname.as_ptr(), buf as *mut libc::c_void, size);
+        #[cfg(target_os = "macos")]
+        let ret = libc::getxattr(
+            cpath.as_ptr(),
+            cname.as_ptr(),
+            buf as *mut libc::c_void,
+            size,
+            0,
+            libc::XATTR_NOFOLLOW,
+        );
+        ret as isize
+    });
+}
+
+/// Returns the names of all Extended Attributes of `path`, not following symlinks
+pub fn list(path: &Path) -> Result<Vec<Vec<u8>>, std::io::Error> {
+    let cpath = cstring(path.as_os_str().as_bytes())?;
+    let data = read_sized(|buf, size| unsafe {
+        #[cfg(target_os = "linux")]
+        let ret = libc::llistxattr(cpath.as_ptr(), buf as *mut libc::c_char, size);
+        #[cfg(target_os = "macos")]
+        let ret = libc::listxattr(
+            cpath.as_ptr(),
+            buf as *mut libc::c_char,
+            size,
+            libc::XATTR_NOFOLLOW,
+        );
+        ret as isize
+    })?;
+    return Ok(data
+        .split(|b| *b == 0)
+        .filter(|n| !n.is_empty())
+        .map(|n| n.to_vec())
+        .collect());
+}
+
+/// Sets the Extended Attribute `name` of `path`, not following symlinks
+pub fn set(path: &Path, name: &[u8], value: &[u8], flags: i32) -> Result<(), std::io::Error> {
+    let cpath = cstring(path.as_os_str().as_bytes())?;
+    let cname = cstring(name)?;
+    let ret = unsafe {
+        #[cfg(target_os = "linux")]
+        let ret = libc::lsetxattr(
+            cpath.as_ptr(),
+            cname.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            flags,
+        );
+        #[cfg(target_os = "macos")]
+        let ret = libc::setxattr(
+            cpath.as_ptr(),
+            cname.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+            flags | libc::XATTR_NOFOLLOW,
+        );
+        ret
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    return Ok(());
+}
+
+/// Removes the Extended Attribute `name` of `path`, not following symlinks
+pub fn remove(path: &Path, name: &[u8]) -> Result<(), std::io::Error> {
+    let cpath = cstring(path.as_os_str().as_bytes())?;
+    let cname = cstring(name)?;
+    let ret = unsafe {
+        #[cfg(target_os = "linux")]
+        let ret = libc::lremovexattr(cpath.as_ptr(), cname.as_ptr());
+        #[cfg(target_os = "macos")]
+        let ret = libc::removexattr(cpath.as_ptr(), cname.as_ptr(), libc::XATTR_NOFOLLOW);
+        ret
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    return Ok(());
+}
@@ -0,0 +1,38 @@
+//! Fixtures shared by the integration tests.
+//! Not every test uses every fixture.
+#![allow(dead_code)]
+
+use shfs_api::FilesystemAPI;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Opens the [FilesystemAPI] of a volume at `root`
+pub fn open(root: &Path) -> FilesystemAPI {
+    return FilesystemAPI::new(root.to_str().unwrap().to_string());
+}
+
+/// Creates a volume in a temporary directory, filled by `populate` before it is opened
+pub fn volume<F: FnOnce(&Path)>(populate: F) -> (TempDir, FilesystemAPI) {
+    let dir = TempDir::new().unwrap();
+    populate(dir.path());
+    let api = open(dir.path());
+    return (dir, api);
+}
+
+/// Creates the volume `name` inside a temporary directory.
+/// `populate` is given the temporary directory, so it can create entries next to the volume as well.
+pub fn volume_in<F: FnOnce(&Path)>(name: &str, populate: F) -> (TempDir, FilesystemAPI) {
+    let dir = TempDir::new().unwrap();
+    fs::create_dir_all(dir.path().join(name)).unwrap();
+    populate(dir.path());
+    let api = open(&dir.path().join(name));
+    return (dir, api);
+}
+
+/// Creates a volume containing `file` with `content`, returning the API and the inode of the file
+pub fn volume_with_file(content: &[u8]) -> (TempDir, FilesystemAPI, u64) {
+    let (dir, api) = volume(|root| fs::write(root.join("file"), content).unwrap());
+    let ino = api.get_entry(Path::new("/file")).unwrap().ino;
+    return (dir, api, ino);
+}
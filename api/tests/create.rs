@@ -0,0 +1,27 @@
+mod common;
+
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+fn mode(path: &std::path::Path) -> u32 {
+    return fs::symlink_metadata(path).unwrap().permissions().mode() & 0o7777;
+}
+
+#[test]
+fn create_uses_requested_mode() {
+    let (dir, api) = common::volume(|_| {});
+    api.create(1, OsStr::new("private"), libc::O_RDWR, 0o600).unwrap();
+    api.create(1, OsStr::new("script"), libc::O_RDWR, 0o700).unwrap();
+    assert_eq!(mode(&dir.path().join("private")), 0o600);
+    assert_eq!(mode(&dir.path().join("script")), 0o700);
+}
+
+#[test]
+fn mkdir_uses_requested_mode() {
+    let (dir, api) = common::volume(|_| {});
+    api.mkdir(1, OsStr::new("private"), 0o700).unwrap();
+    api.mkdir(1, OsStr::new("shared"), 0o755).unwrap();
+    assert_eq!(mode(&dir.path().join("private")), 0o700);
+    assert_eq!(mode(&dir.path().join("shared")), 0o755);
+}
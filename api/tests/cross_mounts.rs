@@ -0,0 +1,81 @@
+mod common;
+
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Bind mount, unmounted when dropped
+struct BindMount {
+    target: PathBuf,
+}
+
+impl BindMount {
+    /// Bind mounts `source` onto `target`, returning None if mounting is not permitted
+    fn new(source: &Path, target: &Path) -> Option<BindMount> {
+        let csource = CString::new(source.as_os_str().as_bytes()).unwrap();
+        let ctarget = CString::new(target.as_os_str().as_bytes()).unwrap();
+        let ret = unsafe {
+            libc::mount(
+                csource.as_ptr(),
+                ctarget.as_ptr(),
+                std::ptr::null(),
+                libc::MS_BIND,
+                std::ptr::null(),
+            )
+        };
+        if ret < 0 {
+            eprintln!("Skipping, can not bind mount: {}", std::io::Error::last_os_error());
+            return None;
+        }
+        return Some(BindMount { target: target.to_path_buf() });
+    }
+}
+
+impl Drop for BindMount {
+    fn drop(&mut self) {
+        let ctarget = CString::new(self.target.as_os_str().as_bytes()).unwrap();
+        unsafe { libc::umount2(ctarget.as_ptr(), libc::MNT_DETACH) };
+    }
+}
+
+/// Bind mounts a directory of the same filesystem at `/mnt` of a volume, so both share the device
+fn setup(cross_mounts: bool) -> Option<(TempDir, TempDir, BindMount, shfs_api::FilesystemAPI)> {
+    let source = TempDir::new().unwrap();
+    fs::write(source.path().join("file"), b"mounted").unwrap();
+    fs::create_dir(source.path().join("sub")).unwrap();
+    let (dir, mut api) = common::volume(|root| {
+        fs::create_dir(root.join("mnt")).unwrap();
+        fs::write(root.join("local"), b"local").unwrap();
+    });
+    let mount = BindMount::new(source.path(), &dir.path().join("mnt"))?;
+    api.set_cross_mounts(cross_mounts);
+    return Some((dir, source, mount, api));
+}
+
+#[test]
+fn bind_mounts_are_refused() {
+    let setup = setup(false);
+    if setup.is_none() {
+        return;
+    }
+    let (_dir, _source, _mount, api) = setup.unwrap();
+    assert!(api.get_entry(Path::new("/local")).is_ok());
+    for path in &["/mnt", "/mnt/file", "/mnt/sub", "/mnt/sub/missing"] {
+        let err = api.get_entry(Path::new(path)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EXDEV), "{}", path);
+    }
+}
+
+#[test]
+fn bind_mounts_are_crossed_if_allowed() {
+    let setup = setup(true);
+    if setup.is_none() {
+        return;
+    }
+    let (_dir, _source, _mount, api) = setup.unwrap();
+    let entry = api.get_entry(Path::new("/mnt/file")).unwrap();
+    assert_eq!(api.read(entry.ino, 0, 64).unwrap(), b"mounted".to_vec());
+    assert!(api.get_entry(Path::new("/mnt/sub")).is_ok());
+}
@@ -0,0 +1,62 @@
+use shfs_api::calls::{Call, Request, RequestInfo};
+use shfs_api::encoding::Encoding;
+use shfs_api::framing::MAX_FRAME_SIZE;
+use shfs_api::protocol::MAX_IO_SIZE;
+use shfs_api::responses::{Reply, Response};
+
+#[test]
+fn json_carries_half_the_io_size() {
+    assert_eq!(Encoding::Cbor.max_io_size(), MAX_IO_SIZE);
+    assert_eq!(Encoding::Json.max_io_size(), MAX_IO_SIZE / 2);
+}
+
+#[test]
+fn maximum_json_read_fits_into_a_frame() {
+    let size = Encoding::Json.max_io_size() as usize;
+    // Every byte spelled with the most characters
+    let reply = Reply {
+        id: u64::MAX,
+        response: Response::Read {
+            data: vec![255; size],
+            eof: true,
+        },
+    };
+    let data = Encoding::Json.encode(&reply).unwrap();
+    assert!(data.len() <= MAX_FRAME_SIZE);
+
+    let reply: Reply = Encoding::Json.decode(&data).unwrap();
+    assert_eq!(reply.id, u64::MAX);
+    match reply.response {
+        Response::Read { data, eof } => {
+            assert_eq!(data, vec![255; size]);
+            assert!(eof);
+        }
+        obj => panic!("unexpected response {:?}", obj),
+    }
+}
+
+#[test]
+fn maximum_json_write_fits_into_a_frame() {
+    let size = Encoding::Json.max_io_size() as usize;
+    let req = Request {
+        id: u64::MAX,
+        call: Call::Write {
+            info: RequestInfo { volume_id: u64::MAX },
+            ino: u64::MAX,
+            fh: Some(u64::MAX),
+            offset: i64::MIN,
+            data: vec![255; size],
+        },
+    };
+    let data = Encoding::Json.encode(&req).unwrap();
+    assert!(data.len() <= MAX_FRAME_SIZE);
+
+    let req: Request = Encoding::Json.decode(&data).unwrap();
+    match req.call {
+        Call::Write { offset, data, .. } => {
+            assert_eq!(offset, i64::MIN);
+            assert_eq!(data, vec![255; size]);
+        }
+        obj => panic!("unexpected call {:?}", obj),
+    }
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use shfs_api::filesystem_entry::{AttrChanges, FsFiletype};
+use shfs_api::FilesystemAPI;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Creates the volume `vol1` next to a sibling volume `vol10` and a directory `outside`,
+/// with symlinks inside the volume pointing out of it in various ways
+fn setup() -> (TempDir, FilesystemAPI) {
+    return common::volume_in("vol1", |dir| {
+        let root = dir.join("vol1");
+        let outside = dir.join("outside");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir_all(dir.join("vol10")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(root.join("file"), b"inside").unwrap();
+        fs::write(dir.join("vol10/file"), b"sibling").unwrap();
+        fs::write(outside.join("secret"), b"secret").unwrap();
+        fs::set_permissions(outside.join("secret"), fs::Permissions::from_mode(0o600)).unwrap();
+
+        symlink(&outside, root.join("abs")).unwrap();
+        symlink("../outside", root.join("rel")).unwrap();
+        symlink("..", root.join("up")).unwrap();
+        symlink("rel", root.join("chain")).unwrap();
+        symlink("../vol10", root.join("sib")).unwrap();
+        symlink("../../outside", root.join("sub/back")).unwrap();
+        symlink("/proc/self/cwd", root.join("magic")).unwrap();
+        symlink(outside.join("secret"), root.join("lnk")).unwrap();
+    });
+}
+
+fn outside(dir: &TempDir, name: &str) -> bool {
+    return dir.path().join("outside").join(name).exists();
+}
+
+#[test]
+fn symlinked_directories_do_not_lead_outside() {
+    let (_dir, api) = setup();
+    for path in &[
+        "/abs/secret",
+        "/rel/secret",
+        "/up/outside/secret",
+        "/chain/secret",
+        "/sib/file",
+        "/sub/back/secret",
+        "/magic/secret",
+    ] {
+        assert!(api.get_entry(Path::new(path)).is_err(), "{} resolved outside of the volume", path);
+    }
+}
+
+#[test]
+fn parent_components_do_not_lead_outside() {
+    let (_dir, api) = setup();
+    for path in &["/../outside/secret", "/sub/../../outside/secret", "/../vol10/file", "../vol1/file"] {
+        let err = api.get_entry(Path::new(path)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(13), "{}", path);
+    }
+    // Staying inside is fine
+    assert_eq!(api.get_entry(Path::new("/sub/../file")).unwrap().size, 6);
+}
+
+#[test]
+fn last_component_is_not_followed() {
+    let (_dir, api) = setup();
+    let entry = api.get_entry(Path::new("/abs")).unwrap();
+    assert!(matches!(entry.kind, FsFiletype::Symlink));
+    let target = api.readlink(entry.ino).unwrap();
+    assert!(target.ends_with("outside"));
+}
+
+#[test]
+fn readdir_does_not_follow_symlinks_outside() {
+    let (_dir, api) = setup();
+    for path in &["/abs", "/rel", "/up", "/chain", "/sib", "/magic", "/sub/back", "/.."] {
+        assert!(api.readdir(Path::new(path)).is_err(), "{} listed outside of the volume", path);
+    }
+    let mut entries = api.readdir(Path::new("/sub")).unwrap();
+    entries.sort();
+    assert_eq!(entries, vec![PathBuf::from("/sub/back")]);
+}
+
+#[test]
+fn file_symlinks_are_not_opened() {
+    let (dir, api) = setup();
+    let ino = api.get_entry(Path::new("/lnk")).unwrap().ino;
+    assert!(api.read(ino, 0, 16).is_err());
+    assert!(api.write(ino, 0, b"pwned").is_err());
+    assert!(api.open(ino, libc::O_RDONLY).is_err());
+    assert!(api.fsync(ino, false).is_err());
+
+    let truncate = AttrChanges {
+        size: Some(0),
+        ..Default::default()
+    };
+    assert!(api.setattr(ino, &truncate).is_err());
+    let chmod = AttrChanges {
+        mode: Some(0o777),
+        ..Default::default()
+    };
+    let err = api.setattr(ino, &chmod).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EOPNOTSUPP));
+
+    let secret = dir.path().join("outside/secret");
+    assert_eq!(fs::read(&secret).unwrap(), b"secret".to_vec());
+    assert_eq!(fs::metadata(&secret).unwrap().permissions().mode() & 0o777, 0o600);
+}
+
+#[test]
+fn entries_are_not_created_through_symlinks() {
+    let (dir, api) = setup();
+    let file = api.get_entry(Path::new("/file")).unwrap().ino;
+    for link in &["/abs", "/rel", "/chain"] {
+        let parent = api.get_entry(Path::new(link)).unwrap().ino;
+        assert!(api.create(parent, OsStr::new("pwned"), libc::O_RDWR, 0o644).is_err());
+        assert!(api.mkdir(parent, OsStr::new("pwned"), 0o755).is_err());
+        assert!(api.symlink(parent, OsStr::new("pwned"), Path::new("/")).is_err());
+        assert!(api.mknod(parent, OsStr::new("pwned"), libc::S_IFIFO | 0o644, 0).is_err());
+        assert!(api.link(file, parent, OsStr::new("pwned")).is_err());
+        assert!(api.rename(1, OsStr::new("file"), parent, OsStr::new("pwned")).is_err());
+        assert!(!outside(&dir, "pwned"), "created through {}", link);
+    }
+    assert!(dir.path().join("vol1/file").exists());
+}
+
+#[test]
+fn entries_are_not_removed_through_symlinks() {
+    let (dir, api) = setup();
+    let parent = api.get_entry(Path::new("/abs")).unwrap().ino;
+    assert!(api.unlink(parent, OsStr::new("secret")).is_err());
+    assert!(api.rename(parent, OsStr::new("secret"), 1, OsStr::new("stolen")).is_err());
+    assert!(outside(&dir, "secret"));
+}
+
+#[test]
+fn directory_replaced_by_symlink_is_not_followed() {
+    let (dir, api) = setup();
+    let root = dir.path().join("vol1");
+    fs::create_dir(root.join("swap")).unwrap();
+    let parent = api.get_entry(Path::new("/swap")).unwrap().ino;
+    fs::remove_dir(root.join("swap")).unwrap();
+    symlink(dir.path().join("outside"), root.join("swap")).unwrap();
+
+    assert!(api.create(parent, OsStr::new("pwned"), libc::O_RDWR, 0o644).is_err());
+    assert!(api.readdir(Path::new("/swap")).is_err());
+    assert!(!outside(&dir, "pwned"));
+}
+
+#[test]
+fn volume_root_is_not_a_prefix_match() {
+    let (dir, api) = setup();
+    let sibling = dir.path().join("vol10");
+    let rel = Path::new("/..").join(sibling.file_name().unwrap());
+    assert!(api.readdir(&rel).is_err());
+    assert!(api.get_entry(Path::new("/sib/file")).is_err());
+}
@@ -0,0 +1,46 @@
+use shfs_api::framing::{read_frame, write_frame, MAX_FRAME_SIZE};
+
+#[tokio::test]
+async fn frames_round_trip() {
+    let mut buf: Vec<u8> = vec![];
+    write_frame(&mut buf, b"first").await.unwrap();
+    write_frame(&mut buf, b"").await.unwrap();
+    write_frame(&mut buf, &[0xff; 1000]).await.unwrap();
+    assert_eq!(&buf[..4], &5u32.to_be_bytes());
+
+    let mut stream = &buf[..];
+    assert_eq!(read_frame(&mut stream).await.unwrap(), Some(b"first".to_vec()));
+    assert_eq!(read_frame(&mut stream).await.unwrap(), Some(vec![]));
+    assert_eq!(read_frame(&mut stream).await.unwrap(), Some(vec![0xff; 1000]));
+    // Closed cleanly between frames
+    assert_eq!(read_frame(&mut stream).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn truncated_header_is_an_error() {
+    let mut stream: &[u8] = &[0, 0];
+    let err = read_frame(&mut stream).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn truncated_payload_is_an_error() {
+    let mut buf: Vec<u8> = vec![];
+    write_frame(&mut buf, b"payload").await.unwrap();
+    let mut stream = &buf[..buf.len() - 1];
+    let err = read_frame(&mut stream).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn oversized_length_is_refused() {
+    let header = ((MAX_FRAME_SIZE + 1) as u32).to_be_bytes();
+    let mut stream = &header[..];
+    let err = read_frame(&mut stream).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    let mut buf: Vec<u8> = vec![];
+    let err = write_frame(&mut buf, &vec![0; MAX_FRAME_SIZE + 1]).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(buf.is_empty());
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use shfs_api::calls::{Call, RequestInfo};
+use shfs_api::encoding::Encoding;
+use shfs_api::responses::Response;
+use shfs_api::FilesystemAPI;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Names which are not valid UTF-8: Latin-1, lone continuation bytes and a truncated sequence
+const NAMES: [&[u8]; 4] = [b"caf\xe9", b"\xff\xfe", b"dir-\x80", b"f\xc3("];
+
+fn name(bytes: &[u8]) -> &OsStr {
+    return OsStr::from_bytes(bytes);
+}
+
+fn path(bytes: &[u8]) -> PathBuf {
+    return Path::new("/").join(name(bytes));
+}
+
+/// Creates a volume with a file of every name, a directory `dir-\x80` containing one of them
+/// and a symlink with an invalid target
+fn setup() -> (TempDir, FilesystemAPI) {
+    return common::volume(|root| {
+        for n in &[NAMES[0], NAMES[1], NAMES[3]] {
+            fs::write(root.join(name(n)), n).unwrap();
+        }
+        fs::create_dir(root.join(name(NAMES[2]))).unwrap();
+        fs::write(root.join(name(NAMES[2])).join(name(NAMES[0])), b"nested").unwrap();
+        std::os::unix::fs::symlink(name(b"target-\xe9"), root.join("link")).unwrap();
+    });
+}
+
+#[test]
+fn readdir_lists_invalid_names() {
+    let (_dir, api) = setup();
+    let mut entries = api.readdir(Path::new("/")).unwrap();
+    entries.sort();
+    let mut expected: Vec<PathBuf> = NAMES.iter().map(|n| path(n)).collect();
+    expected.push(PathBuf::from("/link"));
+    expected.sort();
+    assert_eq!(entries, expected);
+
+    let nested = api.readdir(&path(NAMES[2])).unwrap();
+    assert_eq!(nested, vec![path(NAMES[2]).join(name(NAMES[0]))]);
+}
+
+#[test]
+fn entries_with_invalid_names() {
+    let (_dir, api) = setup();
+    for n in &[NAMES[0], NAMES[1], NAMES[3]] {
+        let entry = api.get_entry(&path(n)).unwrap();
+        assert_eq!(entry.path, path(n));
+        assert_eq!(api.read(entry.ino, 0, 64).unwrap(), n.to_vec());
+        assert_eq!(api.get_path_from_inode(entry.ino).unwrap(), path(n));
+    }
+    let nested = api.get_entry(&path(NAMES[2]).join(name(NAMES[0]))).unwrap();
+    assert_eq!(api.read(nested.ino, 0, 64).unwrap(), b"nested".to_vec());
+}
+
+#[test]
+fn modifying_calls_with_invalid_names() {
+    let (dir, api) = setup();
+    let parent = api.get_entry(&path(NAMES[2])).unwrap().ino;
+
+    let (created, _) = api.create(parent, name(b"new-\xfe"), libc::O_RDWR, 0o644).unwrap();
+    api.write(created.ino, 0, b"data").unwrap();
+    let sub = api.mkdir(parent, name(b"sub-\xe9"), 0o755).unwrap();
+    api.rename(parent, name(b"new-\xfe"), sub.ino, name(b"moved-\xff")).unwrap();
+    let moved = path(NAMES[2]).join(name(b"sub-\xe9")).join(name(b"moved-\xff"));
+    assert_eq!(api.get_entry_from_inode(created.ino).unwrap().path, moved);
+    assert_eq!(fs::read(dir.path().join(moved.strip_prefix("/").unwrap())).unwrap(), b"data".to_vec());
+
+    let linked = api.link(created.ino, 1, name(b"hard-\x80")).unwrap();
+    assert_eq!(linked.ino, created.ino);
+    api.unlink(1, name(b"hard-\x80")).unwrap();
+    api.unlink(sub.ino, name(b"moved-\xff")).unwrap();
+    api.rmdir(parent, name(b"sub-\xe9")).unwrap();
+    assert_eq!(fs::read_dir(dir.path().join(name(NAMES[2]))).unwrap().count(), 1);
+}
+
+#[test]
+fn symlink_targets_are_bytes() {
+    let (_dir, api) = setup();
+    let link = api.get_entry(Path::new("/link")).unwrap();
+    assert_eq!(api.readlink(link.ino).unwrap(), PathBuf::from(name(b"target-\xe9")));
+
+    let created = api.symlink(1, name(b"ln-\xff"), Path::new(name(b"../\xfe"))).unwrap();
+    assert_eq!(api.readlink(created.ino).unwrap(), PathBuf::from(name(b"../\xfe")));
+}
+
+#[test]
+fn invalid_names_survive_encoding() {
+    let (_dir, api) = setup();
+    let entry = api.get_entry(&path(NAMES[1])).unwrap();
+    for encoding in &[Encoding::Json, Encoding::Cbor] {
+        let call = Call::Mkdir {
+            info: RequestInfo { volume_id: 0 },
+            parent: 1,
+            name: name(NAMES[0]).to_os_string(),
+            mode: 0o755,
+        };
+        let decoded: Call = encoding.decode(&encoding.encode(&call).unwrap()).unwrap();
+        match decoded {
+            Call::Mkdir { name: n, .. } => assert_eq!(n, name(NAMES[0])),
+            _ => panic!("decoded {:?}", decoded),
+        }
+
+        let response = Response::GetEntry { data: entry.clone() };
+        let decoded: Response = encoding.decode(&encoding.encode(&response).unwrap()).unwrap();
+        match decoded {
+            Response::GetEntry { data } => assert_eq!(data.path, path(NAMES[1])),
+            _ => panic!("decoded {:?}", decoded),
+        }
+
+        let listing = Response::ReadDir { data: api.readdir(Path::new("/")).unwrap() };
+        let decoded: Response = encoding.decode(&encoding.encode(&listing).unwrap()).unwrap();
+        match decoded {
+            Response::ReadDir { data } => assert_eq!(data.len(), NAMES.len() + 1),
+            _ => panic!("decoded {:?}", decoded),
+        }
+
+        let listing = Response::ReadDirPlus { data: api.readdirplus(1).unwrap() };
+        let decoded: Response = encoding.decode(&encoding.encode(&listing).unwrap()).unwrap();
+        match decoded {
+            Response::ReadDirPlus { data } => assert!(data.iter().any(|e| e.name == name(NAMES[1]))),
+            _ => panic!("decoded {:?}", decoded),
+        }
+    }
+}
+
+#[test]
+fn paths_sent_as_strings_are_accepted() {
+    let data = r#"{"kind":"get_entry","info":{"volume_id":0},"path":"/café"}"#;
+    let call: Call = Encoding::Json.decode(data.as_bytes()).unwrap();
+    match call {
+        Call::GetEntry { path, .. } => assert_eq!(path, Path::new("/café")),
+        _ => panic!("decoded {:?}", call),
+    }
+}
+
+#[test]
+fn inode_table_keeps_invalid_names() {
+    let (dir, _) = setup();
+    let state = TempDir::new().unwrap();
+    let state = state.path().join("inodes");
+    let root = dir.path().to_str().unwrap().to_string();
+
+    let api = FilesystemAPI::with_state(root.clone(), state.to_str().unwrap());
+    let ino = api.get_entry(&path(NAMES[2]).join(name(NAMES[0]))).unwrap().ino;
+    drop(api);
+
+    let api = FilesystemAPI::with_state(root, state.to_str().unwrap());
+    let entry = api.get_entry_from_inode(ino).unwrap();
+    assert_eq!(entry.path, path(NAMES[2]).join(name(NAMES[0])));
+}
+
+#[test]
+fn xattr_names_are_bytes() {
+    let (_dir, api) = setup();
+    let ino = api.get_entry(&path(NAMES[0])).unwrap().ino;
+    let xattr = name(b"user.caf\xe9");
+    let err = api.setxattr(ino, xattr, b"value", 0);
+    if err.is_err() && err.as_ref().unwrap_err().raw_os_error() == Some(libc::EOPNOTSUPP) {
+        eprintln!("Skipping, extended attributes are not supported");
+        return;
+    }
+    err.unwrap();
+    assert_eq!(api.getxattr(ino, xattr).unwrap(), b"value".to_vec());
+    assert!(api.listxattr(ino).unwrap().contains(&xattr.as_bytes().to_vec()));
+    api.removexattr(ino, xattr).unwrap();
+    assert!(api.getxattr(ino, xattr).is_err());
+
+    for encoding in &[Encoding::Json, Encoding::Cbor] {
+        let call = Call::GetXattr {
+            info: RequestInfo { volume_id: 0 },
+            ino,
+            name: xattr.to_os_string(),
+            size: 0,
+        };
+        let decoded: Call = encoding.decode(&encoding.encode(&call).unwrap()).unwrap();
+        decoded.validate().unwrap();
+        match decoded {
+            Call::GetXattr { name: n, .. } => assert_eq!(n, xattr),
+            _ => panic!("decoded {:?}", decoded),
+        }
+    }
+}
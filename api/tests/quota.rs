@@ -0,0 +1,74 @@
+mod common;
+
+use shfs_api::filesystem_entry::FsStats;
+use std::fs;
+
+fn stats(blocks: u64, free: u64) -> FsStats {
+    return FsStats {
+        blocks,
+        bfree: free,
+        bavail: free,
+        files: 100,
+        ffree: 50,
+        bsize: 4096,
+        namelen: 255,
+        frsize: 4096,
+    };
+}
+
+#[test]
+fn quota_subtracts_used_space() {
+    // 100 blocks quota of which 30 are used, on a large mostly free filesystem
+    let s = stats(1_000_000, 900_000).with_quota(100 * 4096, 30 * 4096);
+    assert_eq!(s.blocks, 100);
+    assert_eq!(s.bfree, 70);
+    assert_eq!(s.bavail, 70);
+
+    // Partially used blocks count as used
+    let s = stats(1_000_000, 900_000).with_quota(100 * 4096, 30 * 4096 + 1);
+    assert_eq!(s.bfree, 69);
+}
+
+#[test]
+fn quota_is_limited_by_backing_filesystem() {
+    let s = stats(1_000, 10).with_quota(100 * 4096, 30 * 4096);
+    assert_eq!(s.blocks, 100);
+    assert_eq!(s.bfree, 10);
+}
+
+#[test]
+fn exceeded_quota_has_no_free_space() {
+    let s = stats(1_000_000, 900_000).with_quota(100 * 4096, 200 * 4096);
+    assert_eq!(s.bfree, 0);
+    assert_eq!(s.bavail, 0);
+}
+
+#[test]
+fn usage_counts_files_once() {
+    let (dir, api) = common::volume(|root| {
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/file"), vec![1; 64 * 1024]).unwrap();
+    });
+    let before = api.usage().unwrap();
+    assert!(before >= 64 * 1024);
+
+    // A hard link takes no additional space
+    fs::hard_link(dir.path().join("sub/file"), dir.path().join("link")).unwrap();
+    assert_eq!(api.usage().unwrap(), before);
+
+    fs::write(dir.path().join("other"), vec![1; 64 * 1024]).unwrap();
+    assert!(api.usage().unwrap() >= before + 64 * 1024);
+}
+
+#[test]
+fn cached_usage_is_not_walked_per_call() {
+    let (dir, api) = common::volume(|root| {
+        fs::write(root.join("file"), vec![1; 64 * 1024]).unwrap();
+    });
+    let before = api.cached_usage().unwrap();
+    assert_eq!(before, api.usage().unwrap());
+
+    fs::write(dir.path().join("other"), vec![1; 64 * 1024]).unwrap();
+    assert_eq!(api.cached_usage().unwrap(), before);
+    assert!(api.usage().unwrap() >= before + 64 * 1024);
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::volume_with_file;
+
+#[test]
+fn read_within_file() {
+    let (_dir, api, ino) = volume_with_file(b"0123456789");
+    assert_eq!(api.read(ino, 2, 4).unwrap(), b"2345".to_vec());
+}
+
+#[test]
+fn short_read_at_end_of_file() {
+    let (_dir, api, ino) = volume_with_file(b"0123456789");
+    assert_eq!(api.read(ino, 6, 100).unwrap(), b"6789".to_vec());
+}
+
+#[test]
+fn read_past_end_of_file_is_empty() {
+    let (_dir, api, ino) = volume_with_file(b"0123456789");
+    assert!(api.read(ino, 10, 16).unwrap().is_empty());
+    assert!(api.read(ino, 1000, 16).unwrap().is_empty());
+}
+
+#[test]
+fn trailing_zero_bytes_are_kept() {
+    let content = [0xff, 0x00, 0x7f, 0x00, 0x00, 0x00];
+    let (_dir, api, ino) = volume_with_file(&content);
+    assert_eq!(api.read(ino, 0, 4096).unwrap(), content.to_vec());
+}
+
+#[test]
+fn unknown_inode_is_an_error() {
+    let (_dir, api, _) = volume_with_file(b"");
+    let err = api.read(u64::MAX, 0, 16).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(2));
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use shfs_api::filesystem_entry::FsFiletype;
+use shfs_api::FilesystemAPI;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Creates a volume with a file, a symlink and a directory containing a file
+fn setup() -> (TempDir, FilesystemAPI) {
+    return common::volume(|root| {
+        fs::write(root.join("file"), b"content").unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub/nested"), b"nested").unwrap();
+        std::os::unix::fs::symlink("file", root.join("link")).unwrap();
+    });
+}
+
+#[test]
+fn lists_entries_with_attributes() {
+    let (_dir, api) = setup();
+    let listing = api.readdirplus(1).unwrap();
+    assert_eq!(listing[0].name, ".");
+    assert_eq!(listing[1].name, "..");
+
+    let mut names: Vec<OsString> = listing[2..].iter().map(|e| e.name.clone()).collect();
+    names.sort();
+    assert_eq!(names, vec!["file", "link", "sub"]);
+
+    for e in &listing[2..] {
+        let entry = api.get_entry(&Path::new("/").join(&e.name)).unwrap();
+        assert_eq!(e.entry.ino, entry.ino);
+        assert_eq!(e.entry.size, entry.size);
+        assert_eq!(e.entry.path, entry.path);
+    }
+    let file = listing.iter().find(|e| e.name == "file").unwrap();
+    assert_eq!(file.entry.size, 7);
+    let link = listing.iter().find(|e| e.name == "link").unwrap();
+    assert!(matches!(link.entry.kind, FsFiletype::Symlink));
+}
+
+#[test]
+fn parent_of_root_is_root() {
+    let (_dir, api) = setup();
+    let listing = api.readdirplus(1).unwrap();
+    assert_eq!(listing[0].entry.ino, 1);
+    assert_eq!(listing[1].entry.ino, 1);
+}
+
+#[test]
+fn subdirectory_lists_its_parent() {
+    let (_dir, api) = setup();
+    let sub = api.get_entry(Path::new("/sub")).unwrap();
+    let listing = api.readdirplus(sub.ino).unwrap();
+    assert_eq!(listing.len(), 3);
+    assert_eq!(listing[0].entry.ino, sub.ino);
+    assert_eq!(listing[1].entry.ino, 1);
+    assert_eq!(listing[2].name, "nested");
+    assert_eq!(listing[2].entry.size, 6);
+}
+
+#[test]
+fn files_are_not_listed() {
+    let (_dir, api) = setup();
+    let file = api.get_entry(Path::new("/file")).unwrap();
+    let err = api.readdirplus(file.ino).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+    assert!(api.readdirplus(u64::MAX).is_err());
+}
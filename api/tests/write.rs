@@ -0,0 +1,65 @@
+mod common;
+
+use common::volume_with_file;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn content(dir: &TempDir) -> Vec<u8> {
+    return fs::read(dir.path().join("file")).unwrap();
+}
+
+#[test]
+fn sequential_writes() {
+    let (dir, api, ino) = volume_with_file(b"");
+    let chunk: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+    for i in 0..8 {
+        let written = api.write(ino, i * 4096, &chunk).unwrap();
+        assert_eq!(written, 4096);
+    }
+    let data = content(&dir);
+    assert_eq!(data.len(), 8 * 4096);
+    for part in data.chunks(4096) {
+        assert_eq!(part, &chunk[..]);
+    }
+}
+
+#[test]
+fn random_offset_writes() {
+    let (dir, api, ino) = volume_with_file(b"");
+    api.write(ino, 0, &[b'a'; 100]).unwrap();
+    assert_eq!(api.write(ino, 10, b"xyz").unwrap(), 3);
+    assert_eq!(api.write(ino, 50, b"\0\0\0").unwrap(), 3);
+
+    let mut expected = vec![b'a'; 100];
+    expected[10..13].copy_from_slice(b"xyz");
+    expected[50..53].copy_from_slice(b"\0\0\0");
+    assert_eq!(content(&dir), expected);
+}
+
+#[test]
+fn append_writes() {
+    let (dir, api, ino) = volume_with_file(b"");
+    let mut expected = vec![];
+    for part in [&b"first "[..], b"second ", b"third"].iter() {
+        let size = api.get_entry(Path::new("/file")).unwrap().size;
+        assert_eq!(api.write(ino, size as i64, part).unwrap(), part.len() as u32);
+        expected.extend_from_slice(part);
+    }
+    assert_eq!(content(&dir), expected);
+}
+
+#[test]
+fn write_past_end_extends_file() {
+    let (dir, api, ino) = volume_with_file(b"");
+    api.write(ino, 0, b"head").unwrap();
+    api.write(ino, 10, b"tail").unwrap();
+    assert_eq!(content(&dir), b"head\0\0\0\0\0\0tail".to_vec());
+}
+
+#[test]
+fn negative_offset_is_rejected() {
+    let (_dir, api, ino) = volume_with_file(b"");
+    let err = api.write(ino, -1, b"data").unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(22));
+}
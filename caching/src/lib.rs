@@ -1,10 +1,12 @@
 pub mod file;
 use shfs_api::{filesystem_entry};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 
 /// Cache for VolumeClient
 pub struct Cache {
-    pub entry_cache: HashMap<String, filesystem_entry::FilesystemEntry>,
+    pub entry_cache: HashMap<PathBuf, filesystem_entry::FilesystemEntry>,
 }
 
 impl Cache {
@@ -15,13 +17,50 @@ impl Cache {
     }
 
     pub fn add_entry(&mut self, e: &filesystem_entry::FilesystemEntry) {
-        self.entry_cache.insert(e.path.to_string(), e.clone());
+        self.entry_cache.insert(e.path.clone(), e.clone());
     }
 
-    pub fn get_entry(&self, path: &str) -> Option<&filesystem_entry::FilesystemEntry> {
+    pub fn get_entry(&self, path: &Path) -> Option<&filesystem_entry::FilesystemEntry> {
         if self.entry_cache.contains_key(path) {
             return Some(self.entry_cache.get(path).unwrap());
         }
         return None;
     }
+
+    /// Removes the entries of the inode `ino` under every path
+    pub fn remove_inode(&mut self, ino: u64) {
+        self.entry_cache.retain(|_, e| e.ino != ino);
+    }
+
+    /// Removes the entry at `path` and every entry below it,
+    /// along with the other paths of their inodes whose link count changed
+    pub fn remove_tree(&mut self, path: &Path) {
+        let inos: HashSet<u64> = self
+            .entry_cache
+            .iter()
+            .filter(|(p, _)| p.starts_with(path))
+            .map(|(_, e)| e.ino)
+            .collect();
+        self.entry_cache.retain(|p, e| !p.starts_with(path) && !inos.contains(&e.ino));
+    }
+
+    /// Removes the entries a call changing `name` in the directory `parent` may have changed:
+    /// the directory itself and the tree at `name`.
+    /// As the entries are cached by path, a `name` in a directory whose path is not cached is removed in every directory.
+    pub fn remove_child(&mut self, parent: u64, name: &OsStr) {
+        let mut paths = vec![];
+        for path in self.entry_cache.keys() {
+            if path.file_name() != Some(name) {
+                continue;
+            }
+            let dir = path.parent().and_then(|dir| self.entry_cache.get(dir));
+            if dir.is_none() || dir.unwrap().ino == parent {
+                paths.push(path.clone());
+            }
+        }
+        for path in paths {
+            self.remove_tree(&path);
+        }
+        self.remove_inode(parent);
+    }
 }
\ No newline at end of file
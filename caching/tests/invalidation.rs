@@ -0,0 +1,81 @@
+use shfs_api::filesystem_entry::FilesystemEntry;
+use shfs_caching::Cache;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+fn entry(path: &str, ino: u64) -> FilesystemEntry {
+    let mut e = FilesystemEntry::from_metadata(PathBuf::from(path), &std::fs::metadata("/").unwrap());
+    e.ino = ino;
+    return e;
+}
+
+/// Returns a cache holding `/`, `/dir`, `/dir/file`, `/dir/sub`, `/dir/sub/nested`,
+/// `/other/file` and `/link`, a hard link of `/dir/file`
+fn setup() -> Cache {
+    let mut cache = Cache::new();
+    let entries = [
+        ("/", 1),
+        ("/dir", 2),
+        ("/dir/file", 3),
+        ("/dir/sub", 4),
+        ("/dir/sub/nested", 5),
+        ("/other", 6),
+        ("/other/file", 7),
+        ("/link", 3),
+    ];
+    for (path, ino) in entries.iter() {
+        cache.add_entry(&entry(path, *ino));
+    }
+    return cache;
+}
+
+fn cached(cache: &Cache, path: &str) -> bool {
+    return cache.get_entry(Path::new(path)).is_some();
+}
+
+#[test]
+fn removing_a_child_removes_its_tree_and_parent() {
+    let mut cache = setup();
+    cache.remove_child(2, OsStr::new("sub"));
+    assert!(!cached(&cache, "/dir/sub"));
+    assert!(!cached(&cache, "/dir/sub/nested"));
+    // The directory changed as well
+    assert!(!cached(&cache, "/dir"));
+    assert!(cached(&cache, "/dir/file"));
+    assert!(cached(&cache, "/"));
+}
+
+#[test]
+fn only_the_child_of_the_parent_is_removed() {
+    let mut cache = setup();
+    cache.remove_child(2, OsStr::new("file"));
+    assert!(!cached(&cache, "/dir/file"));
+    assert!(cached(&cache, "/other/file"));
+}
+
+#[test]
+fn other_links_of_a_removed_inode_are_removed() {
+    let mut cache = setup();
+    cache.remove_child(2, OsStr::new("file"));
+    assert!(!cached(&cache, "/link"));
+
+    let mut cache = setup();
+    cache.remove_inode(3);
+    assert!(!cached(&cache, "/dir/file"));
+    assert!(!cached(&cache, "/link"));
+    assert!(cached(&cache, "/dir"));
+}
+
+#[test]
+fn children_of_unknown_directories_are_removed_everywhere() {
+    let mut cache = setup();
+    cache.remove_child(42, OsStr::new("file"));
+    assert!(cached(&cache, "/dir/file"));
+    assert!(cached(&cache, "/other/file"));
+
+    cache.remove_inode(2);
+    cache.remove_inode(6);
+    cache.remove_child(42, OsStr::new("file"));
+    assert!(!cached(&cache, "/dir/file"));
+    assert!(!cached(&cache, "/other/file"));
+}
@@ -1,104 +1,330 @@
 use rich::*;
-use shfs_api::calls::{RequestInfo, Call};
-use shfs_api::responses::Response;
+use shfs_api::calls::{RequestInfo, Call, Request};
+use shfs_api::encoding::Encoding;
+use shfs_api::error::{ErrorKind, ShfsError};
+use shfs_api::framing::{read_frame, write_frame};
+use shfs_api::lock::{FileLock, LockKind};
+use shfs_api::protocol::{Capabilities, CALL_LOCKS, CALL_READDIRPLUS, CALL_XATTR, PROTOCOL_VERSION};
+use shfs_api::xattr::XattrReply;
+use shfs_api::responses::{Reply, Response};
 use shfs_api::{filesystem_entry};
 use shfs_caching;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
 use tokio::runtime::Runtime;
-use std::str::from_utf8;
-
-/// Removing unnecessary zeros at the end of [Vec]
-fn remove_last_zeros(d: Vec<u8>) -> Vec<u8> {
-    let mut reversed = d.clone();
-    reversed.reverse();
-    let mut c = 0;
-    for e in reversed.iter() {
-        if *e as isize != 0 {
-            break;
+use tokio::sync::oneshot;
+
+/// Waiters for the [Response]s of in-flight requests.
+/// `None` once the session is closed.
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Response>>>>>;
+
+/// Sessions the locks of each owner on a file were taken in, by inode and owner
+type LockSessions = Arc<Mutex<HashMap<(u64, u64), u64>>>;
+
+/// Session on a single [TcpStream] carrying many requests
+struct Session {
+    /// Id of the session, handles and locks taken in it are only valid within it
+    id: u64,
+    writer: tokio::sync::Mutex<OwnedWriteHalf>,
+    pending: Pending,
+    encoding: Encoding,
+    capabilities: Capabilities,
+}
+
+impl Session {
+    /// Returns true if the underlying connection is still open
+    fn is_alive(&self) -> bool {
+        return self.pending.lock().unwrap().is_some();
+    }
+
+    /// Sends a serialized [Request] returning the receiver of its [Response]
+    async fn send(
+        &self,
+        id: u64,
+        msg: &[u8],
+    ) -> Result<oneshot::Receiver<Response>, std::io::Error> {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.is_none() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotConnected,
+                    "Session closed",
+                ));
+            }
+            pending.as_mut().unwrap().insert(id, tx);
         }
-        c += 1;
+
+        let mut writer = self.writer.lock().await;
+        let err = write_frame(&mut *writer, msg).await;
+        if err.is_err() {
+            let mut pending = self.pending.lock().unwrap();
+            if pending.is_some() {
+                pending.as_mut().unwrap().remove(&id);
+            }
+            return Err(err.unwrap_err());
+        }
+        return Ok(rx);
+    }
+
+    /// Receives [Reply]s and hands them to the waiting requests until the connection closes
+    /// or a reply can not be decoded
+    async fn receive(mut reader: OwnedReadHalf, pending: Pending, encoding: Encoding) {
+        loop {
+            let data = match read_frame(&mut reader).await {
+                Ok(Some(data)) => data,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("failed to read from socket; err = {:?}", e);
+                    break;
+                }
+            };
+            let reply: Result<Reply, std::io::Error> = encoding.decode(&data);
+            if reply.is_err() {
+                // The request it answers is unknown, closing the session fails every waiting request
+                eprintln!("Error parsing response: {}", reply.unwrap_err());
+                break;
+            }
+            let reply = reply.unwrap();
+            let response = TCPConnection::decompress(reply.response, encoding);
+            let waiter = match pending.lock().unwrap().as_mut() {
+                Some(waiters) => waiters.remove(&reply.id),
+                None => None,
+            };
+            if waiter.is_some() {
+                let _ = waiter.unwrap().send(response);
+            }
+        }
+        // Dropping the senders wakes up every request still waiting
+        *pending.lock().unwrap() = None;
     }
-    let content = d.len() - c;
-    return d[0..content].to_vec();
 }
 
-/// Wrapper of [TcpStream]
+/// Converts a [Response] not answering a call successfully into an error
+fn error_response(obj: Response) -> std::io::Error {
+    let err = match obj {
+        Response::IOError { error } => error,
+        Response::Error { error } => ShfsError::new(ErrorKind::Protocol, &error),
+        _ => ShfsError::new(ErrorKind::Protocol, "Unexpected response"),
+    };
+    return err.into();
+}
+
+/// Persistent connection to the server multiplexing [Call]s over one [TcpStream]
 pub struct TCPConnection {
     addr: String,
-    socket: TcpStream,
+    encoding: Encoding,
     rt: Runtime,
+    session: Mutex<Option<Arc<Session>>>,
+    next_id: AtomicU64,
+    next_session: AtomicU64,
 }
 
 impl TCPConnection {
-    pub fn new(addr: &String) -> TCPConnection {
-        let rt = Runtime::new().unwrap();
-        let socket = unwrap_or_err(rt.block_on(TcpStream::connect(&addr)), "");
-        return TCPConnection {
+    /// Creates a new [TCPConnection]
+    /// # Arguments
+    /// * `addr` - Address of the Server: IP:PORT
+    /// * `encoding` - Preferred [Encoding] of the connection
+    pub fn new(addr: &String, encoding: Encoding) -> TCPConnection {
+        let con = TCPConnection {
             addr: addr.to_string(),
-            socket,
-            rt,
+            encoding,
+            rt: Runtime::new().unwrap(),
+            session: Mutex::new(None),
+            next_id: AtomicU64::new(1),
+            next_session: AtomicU64::new(1),
         };
+        unwrap_or_err(con.reconnect(), "");
+        return con;
     }
 
-    fn reconnect(&mut self) {
-        let socket = self.rt.block_on(TcpStream::connect(&self.addr)).unwrap();
-        self.socket = socket;
+    /// Opens a new [Session] replacing the current one
+    fn reconnect(&self) -> Result<Arc<Session>, std::io::Error> {
+        let mut stream = self.rt.block_on(TcpStream::connect(&self.addr))?;
+        let capabilities = self.rt.block_on(TCPConnection::handshake(&mut stream, self.encoding))?;
+        let encoding = capabilities.encoding();
+        let (reader, writer) = stream.into_split();
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        self.rt.spawn(Session::receive(reader, pending.clone(), encoding));
+        let session = Arc::new(Session {
+            id: self.next_session.fetch_add(1, Ordering::Relaxed),
+            writer: tokio::sync::Mutex::new(writer),
+            pending,
+            encoding,
+            capabilities,
+        });
+        *self.session.lock().unwrap() = Some(session.clone());
+        return Ok(session);
     }
 
-    async fn send(socket: &mut TcpStream, msg: &Vec<u8>) -> Result<Vec<u8>, std::io::Error> {
-        unwrap_or_err(socket.write_all(msg).await, "Error sending request");
+    /// Performs the handshake on a fresh connection returning the negotiated [Capabilities]
+    async fn handshake(
+        stream: &mut TcpStream,
+        encoding: Encoding,
+    ) -> Result<Capabilities, std::io::Error> {
+        let mut requested = Capabilities::supported();
+        requested.encodings = vec![encoding];
+        if encoding != Encoding::default() {
+            requested.encodings.push(Encoding::default());
+        }
+        let req = Request {
+            id: 0,
+            call: Call::Handshake {
+                version: PROTOCOL_VERSION,
+                capabilities: requested,
+            },
+        };
+        write_frame(stream, &Encoding::default().encode(&req)?).await?;
+        let resp = read_frame(stream).await?;
+        if resp.is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Connection closed during handshake",
+            ));
+        }
+        let reply: Reply = Encoding::default().decode(&resp.unwrap())?;
+        return match reply.response {
+            Response::Handshake { capabilities, .. } => Ok(capabilities),
+            Response::Error { error } => Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionRefused,
+                format!("Server refused connection: {}", error),
+            )),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Invalid handshake response",
+            )),
+        };
+    }
 
-        let mut final_buf: Vec<u8> = Vec::with_capacity(1024);
+    /// Returns the [Capabilities] negotiated with the server
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        let session = self.session.lock().unwrap();
+        return session.as_ref().map(|s| s.capabilities.clone());
+    }
 
-        loop {
-            let mut buf = [0; 1024];
-            let n = socket.read(&mut buf).await;
+    /// Returns the maximum size of a single read or write with the negotiated [Encoding].
+    /// Before the handshake the default [Encoding] is assumed.
+    pub fn max_io_size(&self) -> u32 {
+        let capabilities = self.capabilities();
+        return capabilities.map_or(Encoding::default().max_io_size(), |c| c.encoding().max_io_size());
+    }
 
-            if n.is_err() {
-                return Err(n.unwrap_err());
+    /// Returns the current [Session], reconnecting if it was closed
+    fn session(&self) -> Result<Arc<Session>, std::io::Error> {
+        let current = self.session.lock().unwrap().clone();
+        if current.is_some() {
+            let current = current.unwrap();
+            if current.is_alive() {
+                return Ok(current);
             }
-            let n = n.unwrap();
-            if n == 0 {
-                break;
+        }
+        return self.reconnect();
+    }
+
+    /// Unpacks a [Response::Compressed]
+    fn decompress(obj: Response, encoding: Encoding) -> Response {
+        return match obj {
+            Response::Compressed { data } => {
+                let resp = zstd::stream::decode_all(&data[0..data.len()]);
+                if resp.is_err() {
+                    return Response::Error {
+                        error: String::from("Error decompressing response"),
+                    };
+                }
+                let resp = encoding.decode(&resp.unwrap());
+                if resp.is_err() {
+                    return Response::Error {
+                        error: String::from("Error parsing response"),
+                    };
+                }
+                resp.unwrap()
             }
-            final_buf.extend_from_slice(&buf[0..n]);
+            _ => obj,
+        };
+    }
+
+    /// Sends a [Call] reconnecting if needed, returning the [Response] and the id of the session it was sent in
+    fn send_with_reconnect(&self, req: Call) -> Result<(Response, u64), std::io::Error> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let req = Request { id, call: req };
+
+        let mut session = self.session()?;
+        let mut rx = self.rt.block_on(session.send(id, &session.encoding.encode(&req)?));
+        if rx.is_err() {
+            // The request never left, so it is safe to retry it on a fresh session
+            session = self.reconnect()?;
+            rx = self.rt.block_on(session.send(id, &session.encoding.encode(&req)?));
         }
-        //let buf_str = from_utf8(&buf).unwrap();
+        return Ok((self.receive(rx?)?, session.id));
+    }
 
-        return Ok(final_buf);
+    /// Sends a [Call] in the session `session` only, returning None if that session is closed
+    fn send_in_session(&self, req: Call, session: u64) -> Option<Result<Response, std::io::Error>> {
+        let current = self.session.lock().unwrap().clone()?;
+        if current.id != session || !current.is_alive() {
+            return None;
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let msg = current.encoding.encode(&Request { id, call: req });
+        if msg.is_err() {
+            return Some(Err(msg.unwrap_err()));
+        }
+        let rx = self.rt.block_on(current.send(id, &msg.unwrap()));
+        if rx.is_err() {
+            return None;
+        }
+        return Some(self.receive(rx.unwrap()));
     }
 
-    fn send_with_reconnect(&mut self, msg: &Vec<u8>) -> Result<Vec<u8>, std::io::Error> {
-        self.reconnect();
-        let resp = self.rt.block_on(TCPConnection::send(&mut self.socket, msg));
-        if resp.is_ok() {
-            let resp = resp.unwrap();
-            return Ok(resp);
-        } else {
-            println!("unable to fullfil request");
-            return Err(resp.unwrap_err());
+    /// Waits for the [Response] of a sent request
+    fn receive(&self, rx: oneshot::Receiver<Response>) -> Result<Response, std::io::Error> {
+        let resp = self.rt.block_on(rx);
+        if resp.is_err() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "Connection closed before receiving response",
+            ));
         }
+        return Ok(resp.unwrap());
     }
 
-    /// Sending a [Call] to the Server returning [Response]
-    pub fn send_call(&mut self, req: Call) -> Response {
-        let req = unwrap_or_err(serde_json::to_vec(&req), "Error serializing call");
-        let resp = self.send_with_reconnect(&req).unwrap();
-        let resp = remove_last_zeros(resp);
-        let mut obj: Response =
-            unwrap_or_err(serde_json::from_slice(&resp), "Error parsing response");
-        // Decompression if Compression is applied
-        obj = match obj {
-            Response::Compressed { data } => {
-                let resp = unwrap_or_err(zstd::stream::decode_all(&data[0..data.len()]), "Error decompressing response");
-                let resp = remove_last_zeros(resp);
-                unwrap_or_err(serde_json::from_slice(&resp), "Error parsing response")
-            }
-            _ => obj,
+    /// Turns a failure to get a [Response] into one
+    fn failed_response(err: std::io::Error) -> Response {
+        println!("unable to fullfil request: {}", err);
+        return Response::IOError {
+            error: ShfsError::from(err).with_context("connection"),
         };
+    }
+
+    /// Sending a [Call] to the Server returning [Response]
+    pub fn send_call(&self, req: Call) -> Response {
+        return self.send_call_session(req).0;
+    }
+
+    /// Sends a [Call] like [send_call](TCPConnection::send_call), also returning the id of the session
+    /// it was sent in. Handles and locks the call takes on the server only exist within that session.
+    pub fn send_call_session(&self, req: Call) -> (Response, Option<u64>) {
+        let resp = self.send_with_reconnect(req);
+        if resp.is_err() {
+            return (TCPConnection::failed_response(resp.unwrap_err()), None);
+        }
+        let (obj, session) = resp.unwrap();
         //println!("{:?}", obj); // TODO : Optional verbosity
-        return obj;
+        return (obj, Some(session));
+    }
+
+    /// Sends a [Call] in the session `session` only, as it refers to handles or locks of that session.
+    /// Returns None without sending anything if the session was closed meanwhile.
+    pub fn send_call_in(&self, req: Call, session: u64) -> Option<Response> {
+        return match self.send_in_session(req, session)? {
+            Ok(obj) => Some(obj),
+            Err(err) => Some(TCPConnection::failed_response(err)),
+        };
     }
 }
 
@@ -109,8 +335,13 @@ pub struct ServerConnection {
 
 impl ServerConnection {
     pub fn new(addr: &String) -> ServerConnection {
+        return ServerConnection::with_encoding(addr, Encoding::Cbor);
+    }
+
+    /// Creates a new [ServerConnection] using a specific [Encoding]
+    pub fn with_encoding(addr: &String, encoding: Encoding) -> ServerConnection {
         return ServerConnection {
-            con: TCPConnection::new(addr),
+            con: TCPConnection::new(addr, encoding),
         };
     }
 
@@ -161,8 +392,11 @@ impl ServerConnection {
 
 /// Connection to Volume
 pub struct VolumeConnection {
-    con: TCPConnection,
+    con: Arc<TCPConnection>,
     info: RequestInfo,
+    /// Sessions the open handles were opened in, the server closes them with their session
+    handles: HashMap<u64, u64>,
+    locks: LockSessions,
     // Optional Volume Caching
     pub cache: Option<shfs_caching::Cache>
 }
@@ -173,161 +407,667 @@ impl VolumeConnection {
     /// * `addr` - Address of the Server: IP:PORT
     /// * `vol_id` - ID of Volume
     pub fn new(addr: &String, vol_id: u64) -> VolumeConnection {
+        return VolumeConnection::with_encoding(addr, vol_id, Encoding::Cbor);
+    }
+
+    /// Creates a new [VolumeConnection] using a specific [Encoding]
+    /// # Arguments
+    /// * `addr` - Address of the Server: IP:PORT
+    /// * `vol_id` - ID of Volume
+    /// * `encoding` - Preferred [Encoding] of the connection
+    pub fn with_encoding(addr: &String, vol_id: u64, encoding: Encoding) -> VolumeConnection {
         return VolumeConnection {
-            con: TCPConnection::new(addr),
+            con: Arc::new(TCPConnection::new(addr, encoding)),
             info: RequestInfo { volume_id: vol_id },
+            handles: HashMap::new(),
+            locks: Arc::new(Mutex::new(HashMap::new())),
             cache: Some(shfs_caching::Cache::new())
         };
     }
 
-    pub fn readdir(&mut self, path: &str) -> Vec<String> {
+    pub fn readdir(&mut self, path: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
         let req = Call::ReadDir {
             info: self.info.clone(),
-            path: path.to_string(),
+            path: path.to_path_buf(),
         };
         let obj = self.con.send_call(req);
         let ret = match obj {
-            Response::ReadDir { data } => data,
-            _ => vec![],
+            Response::ReadDir { data } => Ok(data),
+            obj => Err(error_response(obj)),
         };
         return ret;
     }
 
-    pub fn read(&mut self, ino: u64, offset: i64, size: u32) -> Result<Vec<u8>, std::io::Error> {
-        let req = Call::Read {
-            info: self.info.clone(),
-            ino,
-            offset,
-            size,
+    /// Lists the directory `ino` with the attributes of its entries, starting with `.` and `..`.
+    /// The entries are added to the cache.
+    /// Servers not supporting the call are asked for the listing and every entry separately.
+    pub fn readdirplus(&mut self, ino: u64) -> Result<Vec<filesystem_entry::DirEntry>, std::io::Error> {
+        let ret = if self.check_call(CALL_READDIRPLUS, ErrorKind::NotImplemented).is_ok() {
+            let req = Call::ReadDirPlus {
+                info: self.info.clone(),
+                ino,
+            };
+            match self.con.send_call(req) {
+                Response::ReadDirPlus { data } => data,
+                obj => return Err(error_response(obj)),
+            }
+        } else {
+            self.readdirplus_separately(ino)?
         };
-        let obj = self.con.send_call(req);
-        let ret = match obj {
-            Response::Read { data } => Ok(data),
-            Response::IOError { error } => Err(std::io::Error::from_raw_os_error(error)),
-            _ => Ok(vec![]),
+        if self.cache.is_some() {
+            let cache = self.cache.as_mut().unwrap();
+            for entry in &ret {
+                cache.add_entry(&entry.entry);
+            }
+        }
+        return Ok(ret);
+    }
+
+    /// Builds the result of [readdirplus](VolumeConnection::readdirplus) from separate calls
+    fn readdirplus_separately(&mut self, ino: u64) -> Result<Vec<filesystem_entry::DirEntry>, std::io::Error> {
+        let dir = self.get_entry_from_inode(ino)?;
+        let parent = match dir.path.parent() {
+            Some(parent) => self.get_entry(parent)?,
+            None => dir.clone(),
         };
-        return ret;
+        let mut ret = vec![
+            filesystem_entry::DirEntry {
+                name: ".".into(),
+                entry: dir.clone(),
+            },
+            filesystem_entry::DirEntry {
+                name: "..".into(),
+                entry: parent,
+            },
+        ];
+        for path in self.readdir(&dir.path)? {
+            let entry = self.get_entry(&path);
+            if entry.is_err() || path.file_name().is_none() {
+                continue;
+            }
+            ret.push(filesystem_entry::DirEntry {
+                name: path.file_name().unwrap().to_os_string(),
+                entry: entry.unwrap(),
+            });
+        }
+        return Ok(ret);
+    }
+
+    /// Reads from a file, through the handle `fh` if it is open.
+    /// Reads larger than the negotiated [Encoding] carries are split, stopping at the first short read.
+    pub fn read(
+        &mut self,
+        ino: u64,
+        fh: Option<u64>,
+        offset: i64,
+        size: u32,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        let max = self.con.max_io_size();
+        let mut ret: Vec<u8> = Vec::new();
+        loop {
+            let want = std::cmp::min(size - ret.len() as u32, max);
+            let req = Call::Read {
+                info: self.info.clone(),
+                ino,
+                fh,
+                offset: offset + ret.len() as i64,
+                size: want,
+            };
+            let (data, eof) = match self.send_on_handle(fh, req) {
+                Response::Read { data, eof } => (data, eof),
+                obj if ret.is_empty() => return Err(error_response(obj)),
+                // Reporting what was read before the error
+                _ => return Ok(ret),
+            };
+            let short = eof || data.len() < want as usize;
+            ret.extend(data);
+            if short || ret.len() == size as usize {
+                return Ok(ret);
+            }
+        }
     }
 
     pub fn rename(
         &mut self,
         parent: u64,
-        name: &str,
+        name: &OsStr,
         nparent: u64,
-        nname: &str,
+        nname: &OsStr,
     ) -> Result<(), std::io::Error> {
         let req = Call::Rename {
             info: self.info.clone(),
             parent,
-            name: name.to_string(),
+            name: name.to_os_string(),
             nparent,
-            nname: nname.to_string(),
+            nname: nname.to_os_string(),
         };
         let obj = self.con.send_call(req);
         let ret = match obj {
             Response::Ok {} => Ok(()),
-            Response::IOError { error } => Err(std::io::Error::from_raw_os_error(error)),
-            _ => Ok(()),
+            obj => Err(error_response(obj)),
         };
+        self.invalidate_child(parent, name);
+        self.invalidate_child(nparent, nname);
         return ret;
     }
 
     pub fn mkdir(
         &mut self,
         parent: u64,
-        name: &str,
+        name: &OsStr,
+        mode: u32,
     ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
         let req = Call::Mkdir {
             info: self.info.clone(),
             parent,
-            name: name.to_string(),
+            name: name.to_os_string(),
+            mode,
         };
         let obj = self.con.send_call(req);
         let ret = match obj {
             Response::GetEntry { data } => Ok(data),
-            Response::IOError { error } => Err(std::io::Error::from_raw_os_error(error)),
-            _ => Ok(filesystem_entry::FilesystemEntry::new_directory(
-                String::from(""),
-                0,
-                0,
-                0,
-                755,
-                0,
-                0,
-            )),
+            obj => Err(error_response(obj)),
         };
+        self.invalidate_child(parent, name);
         return ret;
     }
 
+    /// Creates and opens a file returning its entry and handle
     pub fn create(
         &mut self,
         parent: u64,
-        name: &str,
-    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
+        name: &OsStr,
+        flags: i32,
+        mode: u32,
+    ) -> Result<(filesystem_entry::FilesystemEntry, u64), std::io::Error> {
         let req = Call::Create {
             info: self.info.clone(),
             parent,
-            name: name.to_string(),
+            name: name.to_os_string(),
+            flags,
+            mode,
         };
-        let obj = self.con.send_call(req);
+        let (obj, session) = self.con.send_call_session(req);
         let ret = match obj {
-            Response::GetEntry { data } => Ok(data),
-            Response::IOError { error } => Err(std::io::Error::from_raw_os_error(error)),
-            _ => Err(std::io::Error::from_raw_os_error(0)),
+            Response::Created { data, fh } => {
+                self.handles.extend(session.map(|session| (fh, session)));
+                Ok((data, fh))
+            }
+            obj => Err(error_response(obj)),
         };
+        self.invalidate_child(parent, name);
         return ret;
     }
 
-    pub fn write(&mut self, ino: u64, offset: i64, data: &[u8]) -> Result<u32, std::io::Error> {
-        println!("client write {} {} {}", ino, offset, from_utf8(&data).unwrap().to_string());
-        let req = Call::Write {
+    /// Opens a file on the server returning its handle
+    pub fn open(&mut self, ino: u64, flags: i32) -> Result<u64, std::io::Error> {
+        let req = Call::Open {
             info: self.info.clone(),
             ino,
-            offset,
-            data: data.to_vec(),
+            flags,
+        };
+        let (obj, session) = self.con.send_call_session(req);
+        let ret = match obj {
+            Response::Open { fh } => {
+                self.handles.extend(session.map(|session| (fh, session)));
+                Ok(fh)
+            }
+            obj => Err(error_response(obj)),
+        };
+        return ret;
+    }
+
+    /// Closes a handle returned by [open](VolumeConnection::open) or [create](VolumeConnection::create)
+    pub fn release(&mut self, fh: u64) -> Result<(), std::io::Error> {
+        let req = Call::Release {
+            info: self.info.clone(),
+            fh,
+        };
+        let obj = match self.handles.remove(&fh) {
+            Some(session) => match self.con.send_call_in(req, session) {
+                Some(obj) => obj,
+                // The server closed the handle with its session
+                None => return Ok(()),
+            },
+            None => self.con.send_call(req),
         };
-        let obj = self.con.send_call(req);
         let ret = match obj {
-            Response::Write { data } => Ok(data),
-            Response::IOError { error } => Err(std::io::Error::from_raw_os_error(error)),
-            _ => Err(std::io::Error::from_raw_os_error(0)),
+            Response::Ok {} => Ok(()),
+            obj => Err(error_response(obj)),
         };
         return ret;
     }
 
-    pub fn unlink(&mut self, parent: u64, name: &str) -> Result<(), std::io::Error> {
+    /// Writes to a file, through the handle `fh` if it is open.
+    /// Writes larger than the negotiated [Encoding] carries are split, stopping at the first short write.
+    pub fn write(
+        &mut self,
+        ino: u64,
+        fh: Option<u64>,
+        offset: i64,
+        data: &[u8],
+    ) -> Result<u32, std::io::Error> {
+        let max = self.con.max_io_size() as usize;
+        let mut written: usize = 0;
+        let ret = loop {
+            let chunk = &data[written..std::cmp::min(data.len(), written + max)];
+            let req = Call::Write {
+                info: self.info.clone(),
+                ino,
+                fh,
+                offset: offset + written as i64,
+                data: chunk.to_vec(),
+            };
+            match self.send_on_handle(fh, req) {
+                Response::Write { data: count } => {
+                    written += count as usize;
+                    if (count as usize) < chunk.len() || written == data.len() {
+                        break Ok(written as u32);
+                    }
+                }
+                obj if written == 0 => break Err(error_response(obj)),
+                // Reporting what was written before the error
+                _ => break Ok(written as u32),
+            }
+        };
+        self.invalidate_inode(ino);
+        return ret;
+    }
+
+    pub fn unlink(&mut self, parent: u64, name: &OsStr) -> Result<(), std::io::Error> {
         let req = Call::Unlink {
             info: self.info.clone(),
             parent,
-            name: name.to_string(),
+            name: name.to_os_string(),
         };
         let obj = self.con.send_call(req);
         let ret = match obj {
             Response::Ok {} => Ok(()),
-            Response::IOError { error } => Err(std::io::Error::from_raw_os_error(error)),
-            _ => Ok(()),
+            obj => Err(error_response(obj)),
         };
+        self.invalidate_child(parent, name);
         return ret;
     }
 
-    pub fn rmdir(&mut self, parent: u64, name: &str) -> Result<(), std::io::Error> {
+    pub fn rmdir(&mut self, parent: u64, name: &OsStr) -> Result<(), std::io::Error> {
         let req = Call::Rmdir {
             info: self.info.clone(),
             parent,
-            name: name.to_string(),
+            name: name.to_os_string(),
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::Ok {} => Ok(()),
+            obj => Err(error_response(obj)),
+        };
+        self.invalidate_child(parent, name);
+        return ret;
+    }
+
+    pub fn setattr(
+        &mut self,
+        ino: u64,
+        changes: &filesystem_entry::AttrChanges,
+    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
+        let changes = changes.clone();
+        let req = Call::SetAttr {
+            info: self.info.clone(),
+            ino,
+            mode: changes.mode,
+            uid: changes.uid,
+            gid: changes.gid,
+            size: changes.size,
+            atime: changes.atime,
+            mtime: changes.mtime,
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::GetEntry { data } => Ok(data),
+            obj => Err(error_response(obj)),
+        };
+        // Updating the cached entry, other paths of the inode are refetched
+        self.invalidate_inode(ino);
+        if ret.is_ok() && self.cache.is_some() {
+            self.cache.as_mut().unwrap().add_entry(ret.as_ref().unwrap());
+        }
+        return ret;
+    }
+
+    pub fn symlink(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        target: &Path,
+    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
+        let req = Call::Symlink {
+            info: self.info.clone(),
+            parent,
+            name: name.to_os_string(),
+            target: target.to_path_buf(),
         };
         let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::GetEntry { data } => Ok(data),
+            obj => Err(error_response(obj)),
+        };
+        self.invalidate_child(parent, name);
+        return ret;
+    }
+
+    pub fn readlink(&mut self, ino: u64) -> Result<PathBuf, std::io::Error> {
+        let req = Call::ReadLink {
+            info: self.info.clone(),
+            ino,
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::ReadLink { data } => Ok(data),
+            obj => Err(error_response(obj)),
+        };
+        return ret;
+    }
+
+    /// Returns the lock conflicting with `lock` or `lock` with [LockKind::Unlock](shfs_api::lock::LockKind::Unlock) if there is none
+    pub fn getlk(&mut self, ino: u64, owner: u64, lock: FileLock) -> Result<FileLock, std::io::Error> {
+        self.check_call(CALL_LOCKS, ErrorKind::NotImplemented)?;
+        let req = Call::GetLock {
+            info: self.info.clone(),
+            ino,
+            owner,
+            lock,
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::Lock { data } => Ok(data),
+            obj => Err(error_response(obj)),
+        };
+        return ret;
+    }
+
+    /// Takes or releases a lock, failing with EAGAIN if a conflicting lock is held
+    pub fn setlk(&mut self, ino: u64, owner: u64, lock: FileLock) -> Result<(), std::io::Error> {
+        self.check_call(CALL_LOCKS, ErrorKind::NotImplemented)?;
+        return VolumeConnection::send_setlk(&self.con, &self.locks, self.info.clone(), ino, owner, lock, false);
+    }
+
+    /// Releases every lock `owner` holds on the file, as closing a file does for POSIX locks.
+    /// Nothing is sent if the server does not support locks, as no lock can be held then.
+    pub fn release_locks(&mut self, ino: u64, owner: u64) -> Result<(), std::io::Error> {
+        if self.check_call(CALL_LOCKS, ErrorKind::NotImplemented).is_err() {
+            return Ok(());
+        }
+        let lock = FileLock {
+            start: 0,
+            end: u64::MAX,
+            kind: LockKind::Unlock,
+            pid: 0,
+        };
+        return self.setlk(ino, owner, lock);
+    }
+
+    /// Waits for a lock on a separate thread calling `done` once it is taken or failed,
+    /// so other calls are not held up while waiting.
+    pub fn setlk_wait<F>(&mut self, ino: u64, owner: u64, lock: FileLock, done: F)
+    where
+        F: FnOnce(Result<(), std::io::Error>) + Send + 'static,
+    {
+        let err = self.check_call(CALL_LOCKS, ErrorKind::NotImplemented);
+        if err.is_err() {
+            done(err);
+            return;
+        }
+        let con = self.con.clone();
+        let locks = self.locks.clone();
+        let info = self.info.clone();
+        std::thread::spawn(move || {
+            done(VolumeConnection::send_setlk(&con, &locks, info, ino, owner, lock, true));
+        });
+    }
+
+    /// Sends a lock request of `owner`, in the session its locks on the file were taken in.
+    /// If that session was closed the server released the locks, taking further locks fails with EIO
+    /// until the owner unlocked the whole file, as closing it does.
+    fn send_setlk(
+        con: &TCPConnection,
+        locks: &LockSessions,
+        info: RequestInfo,
+        ino: u64,
+        owner: u64,
+        lock: FileLock,
+        sleep: bool,
+    ) -> Result<(), std::io::Error> {
+        let unlock = lock.kind == LockKind::Unlock;
+        let unlock_all = unlock && lock.start == 0 && lock.end == u64::MAX;
+        let req = Call::SetLock {
+            info,
+            ino,
+            owner,
+            lock,
+            sleep,
+        };
+        let taken = locks.lock().unwrap().get(&(ino, owner)).copied();
+        let obj = match taken {
+            Some(session) => match con.send_call_in(req, session) {
+                Some(obj) => obj,
+                None if unlock => {
+                    if unlock_all {
+                        locks.lock().unwrap().remove(&(ino, owner));
+                    }
+                    return Ok(());
+                }
+                None => {
+                    let err = ShfsError::new(ErrorKind::Io, "Locks lost with the connection to the server");
+                    return Err(err.with_context("set_lock").into());
+                }
+            },
+            None => {
+                let (obj, session) = con.send_call_session(req);
+                if matches!(obj, Response::Ok {}) && !unlock && session.is_some() {
+                    locks.lock().unwrap().insert((ino, owner), session.unwrap());
+                }
+                obj
+            }
+        };
+        let ret = match obj {
+            Response::Ok {} => Ok(()),
+            obj => Err(error_response(obj)),
+        };
+        if ret.is_ok() && unlock_all {
+            locks.lock().unwrap().remove(&(ino, owner));
+        }
+        return ret;
+    }
+
+    /// Sends a [Call] using the handle `fh` in the session the handle was opened in.
+    /// Fails with ESTALE if that session was closed, as the server closed the handle with it.
+    fn send_on_handle(&self, fh: Option<u64>, req: Call) -> Response {
+        let session = fh.and_then(|fh| self.handles.get(&fh).copied());
+        if session.is_none() {
+            return self.con.send_call(req);
+        }
+        let name = req.name();
+        return match self.con.send_call_in(req, session.unwrap()) {
+            Some(obj) => obj,
+            None => Response::IOError {
+                error: ShfsError::new(ErrorKind::Stale, "Handle lost with the connection to the server")
+                    .with_context(name),
+            },
+        };
+    }
+
+    /// Flushes a file to disk on the server, through the handle `fh` if it is open
+    pub fn fsync(&mut self, ino: u64, fh: Option<u64>, datasync: bool) -> Result<(), std::io::Error> {
+        let req = Call::Fsync {
+            info: self.info.clone(),
+            ino,
+            fh,
+            datasync,
+        };
+        let obj = self.send_on_handle(fh, req);
         let ret = match obj {
             Response::Ok {} => Ok(()),
-            Response::IOError { error } => Err(std::io::Error::from_raw_os_error(error)),
-            _ => Ok(()),
+            obj => Err(error_response(obj)),
+        };
+        return ret;
+    }
+
+    pub fn statfs(&mut self) -> Result<filesystem_entry::FsStats, std::io::Error> {
+        let req = Call::StatFs {
+            info: self.info.clone(),
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::StatFs { data } => Ok(data),
+            obj => Err(error_response(obj)),
         };
         return ret;
     }
 
+    pub fn mknod(
+        &mut self,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        rdev: u32,
+    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
+        let req = Call::Mknod {
+            info: self.info.clone(),
+            parent,
+            name: name.to_os_string(),
+            mode,
+            rdev,
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::GetEntry { data } => Ok(data),
+            obj => Err(error_response(obj)),
+        };
+        self.invalidate_child(parent, name);
+        return ret;
+    }
+
+    pub fn link(
+        &mut self,
+        ino: u64,
+        nparent: u64,
+        nname: &OsStr,
+    ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
+        let req = Call::Link {
+            info: self.info.clone(),
+            ino,
+            nparent,
+            nname: nname.to_os_string(),
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::GetEntry { data } => Ok(data),
+            obj => Err(error_response(obj)),
+        };
+        self.invalidate_inode(ino);
+        self.invalidate_child(nparent, nname);
+        return ret;
+    }
+
+    /// Fails with `kind` if the server does not support the optional `call`
+    fn check_call(&self, call: &str, kind: ErrorKind) -> Result<(), std::io::Error> {
+        let capabilities = self.con.capabilities();
+        if capabilities.is_none() || !capabilities.unwrap().has_call(call) {
+            let err = ShfsError::new(kind, "Server does not support the call").with_context(call);
+            return Err(err.into());
+        }
+        return Ok(());
+    }
+
+    /// Removes the cached entries of an inode whose attributes changed
+    fn invalidate_inode(&mut self, ino: u64) {
+        if self.cache.is_some() {
+            self.cache.as_mut().unwrap().remove_inode(ino);
+        }
+    }
+
+    /// Removes the cached entries changed by a call on `name` in the directory `parent`
+    fn invalidate_child(&mut self, parent: u64, name: &OsStr) {
+        if self.cache.is_some() {
+            self.cache.as_mut().unwrap().remove_child(parent, name);
+        }
+    }
+
+    pub fn getxattr(&mut self, ino: u64, name: &OsStr, size: u32) -> Result<XattrReply, std::io::Error> {
+        self.check_call(CALL_XATTR, ErrorKind::NotSupported)?;
+        let req = Call::GetXattr {
+            info: self.info.clone(),
+            ino,
+            name: name.to_os_string(),
+            size,
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::Xattr { data } => Ok(XattrReply::Data(data)),
+            Response::XattrSize { size } => Ok(XattrReply::Size(size)),
+            obj => Err(error_response(obj)),
+        };
+        return ret;
+    }
+
+    pub fn listxattr(&mut self, ino: u64, size: u32) -> Result<XattrReply, std::io::Error> {
+        self.check_call(CALL_XATTR, ErrorKind::NotSupported)?;
+        let req = Call::ListXattr {
+            info: self.info.clone(),
+            ino,
+            size,
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::Xattr { data } => Ok(XattrReply::Data(data)),
+            Response::XattrSize { size } => Ok(XattrReply::Size(size)),
+            obj => Err(error_response(obj)),
+        };
+        return ret;
+    }
+
+    pub fn setxattr(
+        &mut self,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        flags: i32,
+    ) -> Result<(), std::io::Error> {
+        self.check_call(CALL_XATTR, ErrorKind::NotSupported)?;
+        let req = Call::SetXattr {
+            info: self.info.clone(),
+            ino,
+            name: name.to_os_string(),
+            value: value.to_vec(),
+            flags,
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::Ok {} => Ok(()),
+            obj => Err(error_response(obj)),
+        };
+        self.invalidate_inode(ino);
+        return ret;
+    }
+
+    pub fn removexattr(&mut self, ino: u64, name: &OsStr) -> Result<(), std::io::Error> {
+        self.check_call(CALL_XATTR, ErrorKind::NotSupported)?;
+        let req = Call::RemoveXattr {
+            info: self.info.clone(),
+            ino,
+            name: name.to_os_string(),
+        };
+        let obj = self.con.send_call(req);
+        let ret = match obj {
+            Response::Ok {} => Ok(()),
+            obj => Err(error_response(obj)),
+        };
+        self.invalidate_inode(ino);
+        return ret;
+    }
+
     pub fn get_entry(
         &mut self,
-        path: &str,
+        path: &Path,
     ) -> Result<filesystem_entry::FilesystemEntry, std::io::Error> {
         // Using cached version if it exists and is enabled
         if self.cache.is_some() {
@@ -339,21 +1079,12 @@ impl VolumeConnection {
         // Calling if nothing is found
         let req = Call::GetEntry {
             info: self.info.clone(),
-            path: path.to_string(),
+            path: path.to_path_buf(),
         };
         let obj = self.con.send_call(req);
         let ret = match obj {
             Response::GetEntry { data } => Ok(data),
-            Response::IOError { error } => Err(std::io::Error::from_raw_os_error(error)),
-            _ => Ok(filesystem_entry::FilesystemEntry::new_directory(
-                String::from(""),
-                0,
-                0,
-                0,
-                755,
-                0,
-                0,
-            )),
+            obj => Err(error_response(obj)),
         };
         // Adding entry to cache
         if ret.is_ok() {
@@ -378,21 +1109,12 @@ impl VolumeConnection {
         let obj = self.con.send_call(req);
         let ret = match obj {
             Response::GetEntry { data } => Ok(data),
-            Response::IOError { error } => Err(std::io::Error::from_raw_os_error(error)),
-            _ => Ok(filesystem_entry::FilesystemEntry::new_directory(
-                String::from(""),
-                0,
-                0,
-                0,
-                755,
-                0,
-                0,
-            )),
+            obj => Err(error_response(obj)),
         };
         return ret;
     }
 
-    pub fn get_path_from_inode(&mut self, ino: u64) -> Result<String, std::io::Error> {
+    pub fn get_path_from_inode(&mut self, ino: u64) -> Result<PathBuf, std::io::Error> {
         let req = Call::GetPathFromInode {
             info: self.info.clone(),
             ino: ino,
@@ -400,8 +1122,7 @@ impl VolumeConnection {
         let obj = self.con.send_call(req);
         let ret = match obj {
             Response::GetPath { data } => Ok(data),
-            Response::IOError { error } => Err(std::io::Error::from_raw_os_error(error)),
-            _ => Ok(String::new()),
+            obj => Err(error_response(obj)),
         };
         return ret;
     }
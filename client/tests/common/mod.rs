@@ -0,0 +1,107 @@
+#![allow(dead_code)]
+
+use shfs_api::config::ServerConfig;
+use shfs_api::error::ShfsError;
+use shfs_client::{ServerConnection, VolumeConnection};
+use shfs_server::FileServer;
+use std::io::Write;
+use std::net::{Shutdown, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::net::TcpListener;
+
+/// Starts a server on its own thread exporting a temporary directory as the volume `v1`.
+/// Returns the directory and the address of the server.
+pub fn server() -> (TempDir, String) {
+    let dir = TempDir::new().unwrap();
+    let config = serde_json::json!({
+        "name": "Test",
+        "volumes": [{ "name": "v1", "root": dir.path().to_str().unwrap() }],
+    });
+    let config: ServerConfig = serde_json::from_value(config).unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+            FileServer::with_config(config, listener).run().await.unwrap();
+        });
+    });
+    return (dir, rx.recv().unwrap().to_string());
+}
+
+/// Connects to the volume `v1` of the server at `addr`
+pub fn connect(addr: &String) -> VolumeConnection {
+    let id = ServerConnection::new(addr).lookup_volume("v1").unwrap();
+    return VolumeConnection::new(addr, id);
+}
+
+/// Creates a file with `content` in the volume at `dir` returning its inode
+pub fn file(dir: &Path, con: &mut VolumeConnection, name: &str, content: &[u8]) -> u64 {
+    std::fs::write(dir.join(name), content).unwrap();
+    return con.get_entry(&Path::new("/").join(name)).unwrap().ino;
+}
+
+/// Returns the errno an error of the client is reported with
+pub fn errno(err: &std::io::Error) -> i32 {
+    return ShfsError::from_io(err).local_errno();
+}
+
+/// Retries `f` until it succeeds, e.g. while a closed connection is noticed
+pub fn eventually<T>(mut f: impl FnMut() -> Result<T, std::io::Error>) -> T {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let ret = f();
+        if ret.is_ok() || Instant::now() > deadline {
+            return ret.unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Forwards connections to a server, so tests can cut them as a failing network would
+pub struct Proxy {
+    pub addr: String,
+    streams: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl Proxy {
+    /// Starts forwarding connections to the server at `target`
+    pub fn new(target: &str) -> Proxy {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let streams = Arc::new(Mutex::new(vec![]));
+        let open = streams.clone();
+        let target = target.to_string();
+        std::thread::spawn(move || {
+            for client in listener.incoming() {
+                let client = client.unwrap();
+                let server = TcpStream::connect(&target).unwrap();
+                let mut open = open.lock().unwrap();
+                open.push(client.try_clone().unwrap());
+                open.push(server.try_clone().unwrap());
+                forward(client.try_clone().unwrap(), server.try_clone().unwrap());
+                forward(server, client);
+            }
+        });
+        return Proxy { addr, streams };
+    }
+
+    /// Cuts every connection forwarded so far, new connections are forwarded again
+    pub fn cut(&self) {
+        for stream in self.streams.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn forward(mut from: TcpStream, mut to: TcpStream) {
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut from, &mut to);
+        let _ = to.flush();
+        let _ = to.shutdown(Shutdown::Both);
+    });
+}
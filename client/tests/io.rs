@@ -0,0 +1,24 @@
+mod common;
+
+use shfs_api::encoding::Encoding;
+use shfs_client::{ServerConnection, VolumeConnection};
+
+#[test]
+fn json_reads_and_writes_beyond_the_encoding_size_are_split() {
+    let (dir, addr) = common::server();
+    let id = ServerConnection::with_encoding(&addr, Encoding::Json).lookup_volume("v1").unwrap();
+    let mut con = VolumeConnection::with_encoding(&addr, id, Encoding::Json);
+    let ino = common::file(dir.path(), &mut con, "big", b"");
+
+    let max = Encoding::Json.max_io_size() as usize;
+    let content: Vec<u8> = (0..max + max / 2).map(|i| (i % 251) as u8).collect();
+    let written = con.write(ino, None, 0, &content).unwrap();
+    assert_eq!(written as usize, content.len());
+    assert_eq!(std::fs::read(dir.path().join("big")).unwrap(), content);
+
+    // Asking for more than the file holds ends at the short read
+    let data = con.read(ino, None, 0, (max * 2) as u32).unwrap();
+    assert_eq!(data, content);
+    let data = con.read(ino, None, 1, max as u32 + 1).unwrap();
+    assert_eq!(data, &content[1..max + 2]);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use shfs_api::lock::{FileLock, LockKind};
+
+fn lock(kind: LockKind) -> FileLock {
+    return FileLock {
+        start: 0,
+        end: 99,
+        kind,
+        pid: 42,
+    };
+}
+
+#[test]
+fn closing_a_file_releases_its_locks() {
+    let (dir, addr) = common::server();
+    let mut a = common::connect(&addr);
+    let mut b = common::connect(&addr);
+    let ino = common::file(dir.path(), &mut a, "file", b"content");
+
+    a.setlk(ino, 1, lock(LockKind::Write)).unwrap();
+    let err = b.setlk(ino, 2, lock(LockKind::Write)).unwrap_err();
+    assert_eq!(common::errno(&err), libc::EAGAIN);
+
+    // What flush sends when the process closes its descriptor
+    a.release_locks(ino, 1).unwrap();
+    b.setlk(ino, 2, lock(LockKind::Write)).unwrap();
+    let err = a.setlk(ino, 1, lock(LockKind::Read)).unwrap_err();
+    assert_eq!(common::errno(&err), libc::EAGAIN);
+}
+
+#[test]
+fn closing_releases_only_the_locks_of_the_owner() {
+    let (dir, addr) = common::server();
+    let mut a = common::connect(&addr);
+    let mut b = common::connect(&addr);
+    let ino = common::file(dir.path(), &mut a, "file", b"content");
+
+    a.setlk(ino, 1, lock(LockKind::Read)).unwrap();
+    a.setlk(ino, 3, lock(LockKind::Read)).unwrap();
+    a.release_locks(ino, 1).unwrap();
+    let err = b.setlk(ino, 2, lock(LockKind::Write)).unwrap_err();
+    assert_eq!(common::errno(&err), libc::EAGAIN);
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use shfs_api::lock::{FileLock, LockKind};
+
+fn lock(kind: LockKind) -> FileLock {
+    return FileLock {
+        start: 0,
+        end: 99,
+        kind,
+        pid: 42,
+    };
+}
+
+#[test]
+fn handles_do_not_survive_a_lost_connection() {
+    let (dir, addr) = common::server();
+    let proxy = common::Proxy::new(&addr);
+    let mut con = common::connect(&proxy.addr);
+    let ino = common::file(dir.path(), &mut con, "file", b"content");
+    let fh = con.open(ino, libc::O_RDWR).unwrap();
+
+    proxy.cut();
+    // The connection is reestablished for calls not bound to the old session
+    common::eventually(|| con.statfs());
+
+    let err = con.read(ino, Some(fh), 0, 7).unwrap_err();
+    assert_eq!(common::errno(&err), libc::ESTALE);
+    let err = con.write(ino, Some(fh), 0, b"changed").unwrap_err();
+    assert_eq!(common::errno(&err), libc::ESTALE);
+    let err = con.fsync(ino, Some(fh), false).unwrap_err();
+    assert_eq!(common::errno(&err), libc::ESTALE);
+    // The server closed the handle with the session already
+    con.release(fh).unwrap();
+
+    let fh = con.open(ino, libc::O_RDONLY).unwrap();
+    assert_eq!(con.read(ino, Some(fh), 0, 7).unwrap(), b"content".to_vec());
+    con.release(fh).unwrap();
+}
+
+#[test]
+fn locks_do_not_survive_a_lost_connection() {
+    let (dir, addr) = common::server();
+    let proxy = common::Proxy::new(&addr);
+    let mut a = common::connect(&proxy.addr);
+    let mut b = common::connect(&addr);
+    let ino = common::file(dir.path(), &mut a, "file", b"content");
+    a.setlk(ino, 1, lock(LockKind::Write)).unwrap();
+
+    proxy.cut();
+    common::eventually(|| a.statfs());
+    // The server released the lock with the session
+    common::eventually(|| b.setlk(ino, 2, lock(LockKind::Write)));
+
+    let err = a.setlk(ino, 1, lock(LockKind::Read)).unwrap_err();
+    assert_eq!(common::errno(&err), libc::EIO);
+    let err = a.setlk(ino, 1, lock(LockKind::Write)).unwrap_err();
+    assert_eq!(common::errno(&err), libc::EIO);
+
+    // Once the file is closed the owner may lock it again
+    a.release_locks(ino, 1).unwrap();
+    let err = a.setlk(ino, 1, lock(LockKind::Read)).unwrap_err();
+    assert_eq!(common::errno(&err), libc::EAGAIN);
+    b.release_locks(ino, 2).unwrap();
+    a.setlk(ino, 1, lock(LockKind::Read)).unwrap();
+}
@@ -6,18 +6,27 @@ use fuse::{
     ReplyEntry, ReplyLock, ReplyOpen, ReplyStatfs, ReplyWrite, ReplyXattr, Request,
 };
 use shfs_client::VolumeConnection;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
 use std::path::Path;
 use time::Timespec;
 
-use shfs_api::filesystem_entry::{FsFiletype, FilesystemEntry};
+use shfs_api::error::ShfsError;
+use shfs_api::filesystem_entry::{AttrChanges, DirEntry, FsFiletype, FsTimespec, FilesystemEntry};
+use shfs_api::lock::{FileLock, LockKind};
+use shfs_api::xattr::XattrReply;
 
 /// Helper Function to convert [FsFiletype] of the API to FUSE [FileType]
 pub fn to_filetype(t: &FsFiletype) -> FileType {
     let kind = match t {
+        FsFiletype::NamedPipe => FileType::NamedPipe,
+        FsFiletype::CharDevice => FileType::CharDevice,
+        FsFiletype::BlockDevice => FileType::BlockDevice,
         FsFiletype::Directory => FileType::Directory,
         FsFiletype::RegularFile => FileType::RegularFile,
-        _ => FileType::RegularFile,
+        FsFiletype::Symlink => FileType::Symlink,
+        FsFiletype::Socket => FileType::Socket,
     };
     return kind;
 }
@@ -34,17 +43,63 @@ pub fn attr(t: &FilesystemEntry) -> FileAttr {
         crtime: t.crtime.ts(),
         kind: to_filetype(&t.kind),
         perm: t.perm,
-        nlink: 0,
+        nlink: t.nlink,
         uid: t.uid,
         gid: t.gid,
-        rdev: 0,
+        rdev: t.rdev,
         flags: 0,
     };
 }
 
+/// Returns the server handle passed by FUSE, which is 0 for files opened without one
+fn handle(fh: u64) -> Option<u64> {
+    if fh == 0 {
+        return None;
+    }
+    return Some(fh);
+}
+
+/// Returns the errno reported to the kernel for an error of the client
+fn errno(err: &std::io::Error) -> i32 {
+    return ShfsError::from_io(err).local_errno();
+}
+
+/// Helper Function answering a getxattr or listxattr request
+fn reply_xattr(value: Result<XattrReply, std::io::Error>, reply: ReplyXattr) {
+    match value {
+        Ok(XattrReply::Size(size)) => reply.size(size),
+        Ok(XattrReply::Data(data)) => reply.data(&data),
+        Err(err) => reply.error(errno(&err)),
+    }
+}
+
 /// FUSE Filesystem for ShFS
 pub struct Filesystem {
     pub api: VolumeConnection,
+    /// Handles written to since they were last flushed
+    written: HashSet<u64>,
+    /// Listings of the open directories by handle, read once on opendir
+    dirs: HashMap<u64, Vec<DirEntry>>,
+    next_dir_handle: u64,
+}
+
+impl Filesystem {
+    pub fn new(api: VolumeConnection) -> Filesystem {
+        return Filesystem {
+            api,
+            written: HashSet::new(),
+            dirs: HashMap::new(),
+            next_dir_handle: 1,
+        };
+    }
+
+    /// Flushes the data written through a handle to disk on the server
+    fn flush_written(&mut self, ino: u64, fh: u64) -> Result<(), std::io::Error> {
+        if !self.written.remove(&fh) {
+            return Ok(());
+        }
+        return self.api.fsync(ino, Some(fh), true);
+    }
 }
 
 impl fuse::Filesystem for Filesystem {
@@ -76,10 +131,9 @@ impl fuse::Filesystem for Filesystem {
         if entry.is_ok() {
             return reply.attr(&Timespec::new(0, 0), &attr(&entry.unwrap()));
         }
-        return reply.error(2);
+        return reply.error(errno(&entry.unwrap_err()));
     }
 
-    // TODO : Implement setattr
     fn setattr(
         &mut self,
         _req: &Request,
@@ -97,21 +151,22 @@ impl fuse::Filesystem for Filesystem {
         _flags: Option<u32>,
         reply: ReplyAttr,
     ) {
-        reply.attr(
-            &Timespec::new(0, 0),
-            &attr(&FilesystemEntry::new_directory(
-                String::from(""),
-                0,
-                0,
-                0,
-                755,
-                501,
-                20,
-            )),
-        );
+        let changes = AttrChanges {
+            mode: _mode,
+            uid: _uid,
+            gid: _gid,
+            size: _size,
+            atime: _atime.as_ref().map(FsTimespec::from_ts),
+            mtime: _mtime.as_ref().map(FsTimespec::from_ts),
+        };
+        let entry = self.api.setattr(_ino, &changes);
+        if entry.is_ok() {
+            reply.attr(&Timespec::new(0, 0), &attr(&entry.unwrap()));
+        } else {
+            reply.error(errno(&entry.unwrap_err()));
+        }
     }
 
-    // TODO : Implement setxattr
     fn setxattr(
         &mut self,
         _req: &Request,
@@ -122,11 +177,14 @@ impl fuse::Filesystem for Filesystem {
         _position: u32,
         reply: ReplyEmpty,
     ) {
-        //self.log.printAttrInfo("Filesystem SetXAttr");
-        reply.ok();
+        let err = self.api.setxattr(_ino, _name, _value, _flags as i32);
+        if err.is_err() {
+            reply.error(errno(&err.unwrap_err()));
+        } else {
+            reply.ok();
+        }
     }
 
-    // TODO : Implement getxattr
     fn getxattr(
         &mut self,
         _req: &Request,
@@ -135,19 +193,22 @@ impl fuse::Filesystem for Filesystem {
         _size: u32,
         reply: ReplyXattr,
     ) {
-        //self.log.printAttrInfo("Filesystem GetXattr");
-        reply.data(&[]);
+        let value = self.api.getxattr(_ino, _name, _size);
+        reply_xattr(value, reply);
     }
 
-    // TODO : Implement listxattr
     fn listxattr(&mut self, _req: &Request, _ino: u64, _size: u32, reply: ReplyXattr) {
-        reply.data(&[]);
+        let names = self.api.listxattr(_ino, _size);
+        reply_xattr(names, reply);
     }
 
-    // TODO : Implement removexattr
     fn removexattr(&mut self, _req: &Request, _ino: u64, _name: &OsStr, reply: ReplyEmpty) {
-        //self.log.printAttrInfo("Filesystem RemoveXAttr");
-        reply.ok();
+        let err = self.api.removexattr(_ino, _name);
+        if err.is_err() {
+            reply.error(errno(&err.unwrap_err()));
+        } else {
+            reply.ok();
+        }
     }
 
     // Actions
@@ -158,20 +219,15 @@ impl fuse::Filesystem for Filesystem {
             _parent,
             _name.to_str().unwrap()
         ));*/
-        let parent_path = self.api.get_path_from_inode(_parent).expect("");
-        let name = _name.to_str().unwrap();
-        let file_path = Path::new(&parent_path).join(name);
-        let file = self.api.get_entry(&file_path.to_str().unwrap());
+        let file = self
+            .api
+            .get_path_from_inode(_parent)
+            .and_then(|parent_path| self.api.get_entry(&parent_path.join(_name)));
         if file.is_err() {
-            let err = file.unwrap_err();
-            if err.kind() == std::io::ErrorKind::NotFound {
-                reply.error(2);
-            } else {
-                reply.error(0);
-            }
+            reply.error(errno(&file.unwrap_err()));
         } else {
             let file = file.unwrap();
-            reply.entry(&Timespec::new(0, 0), &attr(&file), 0);
+            reply.entry(&Timespec::new(0, 0), &attr(&file), file.generation);
         }
     }
 
@@ -179,12 +235,15 @@ impl fuse::Filesystem for Filesystem {
         //self.log.printInfo("Filesystem Forget");
     }
 
-    // TODO : Implement readlink
     fn readlink(&mut self, _req: &Request, _ino: u64, reply: ReplyData) {
-        reply.error(0);
+        let target = self.api.readlink(_ino);
+        if target.is_ok() {
+            reply.data(target.unwrap().as_os_str().as_bytes());
+        } else {
+            reply.error(errno(&target.unwrap_err()));
+        }
     }
 
-    // TODO : Implement mknod
     fn mknod(
         &mut self,
         _req: &Request,
@@ -194,22 +253,26 @@ impl fuse::Filesystem for Filesystem {
         _rdev: u32,
         reply: ReplyEntry,
     ) {
-        reply.error(0);
+        let node = self.api.mknod(_parent, _name, _mode, _rdev);
+        if node.is_ok() {
+            let node = node.unwrap();
+            reply.entry(&Timespec::new(0, 0), &attr(&node), node.generation);
+        } else {
+            reply.error(errno(&node.unwrap_err()));
+        }
     }
 
     fn unlink(&mut self, _req: &Request, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
         /*self.log
         .printAction(&format!("Filesystem Unlink {}", _name.to_str().unwrap()));*/
-        let err = self.api.unlink(_parent, _name.to_str().unwrap());
+        let err = self.api.unlink(_parent, _name);
         if err.is_err() {
-            let err = err.unwrap_err();
-            reply.error(err.raw_os_error().unwrap());
+            reply.error(errno(&err.unwrap_err()));
         } else {
             reply.ok();
         }
     }
 
-    // TODO : Implement symlink
     fn symlink(
         &mut self,
         _req: &Request,
@@ -218,7 +281,13 @@ impl fuse::Filesystem for Filesystem {
         _link: &Path,
         reply: ReplyEntry,
     ) {
-        reply.error(0);
+        let link = self.api.symlink(_parent, _name, _link);
+        if link.is_ok() {
+            let link = link.unwrap();
+            reply.entry(&Timespec::new(0, 0), &attr(&link), link.generation);
+        } else {
+            reply.error(errno(&link.unwrap_err()));
+        }
     }
 
     fn rename(
@@ -235,14 +304,9 @@ impl fuse::Filesystem for Filesystem {
             _name.to_str().unwrap(),
             _newname.to_str().unwrap()
         ));*/
-        let err = self.api.rename(
-            _parent,
-            _name.to_str().unwrap(),
-            _newparent,
-            _newname.to_str().unwrap(),
-        );
+        let err = self.api.rename(_parent, _name, _newparent, _newname);
         if err.is_err() {
-            reply.error(err.unwrap_err().raw_os_error().unwrap());
+            reply.error(errno(&err.unwrap_err()));
         } else {
             reply.ok();
         }
@@ -258,20 +322,20 @@ impl fuse::Filesystem for Filesystem {
     ) {
         /*self.log
         .printAction(&format!("Filesystem MkDir {}", _name.to_str().unwrap()));*/
-        let dir = self.api.mkdir(_parent, _name.to_str().expect(""));
+        let dir = self.api.mkdir(_parent, _name, _mode);
         if dir.is_ok() {
-            reply.entry(&Timespec::new(0, 0), &attr(&dir.unwrap()), 0);
+            let dir = dir.unwrap();
+            reply.entry(&Timespec::new(0, 0), &attr(&dir), dir.generation);
         } else {
-            reply.error(dir.unwrap_err().raw_os_error().unwrap());
+            reply.error(errno(&dir.unwrap_err()));
         }
     }
     fn rmdir(&mut self, _req: &Request, _parent: u64, _name: &OsStr, reply: ReplyEmpty) {
         /*self.log
         .printAction(&format!("Filesystem RmDir {}", _name.to_str().unwrap()));*/
-        let err = self.api.rmdir(_parent, _name.to_str().unwrap());
+        let err = self.api.rmdir(_parent, _name);
         if err.is_err() {
-            let err = err.unwrap_err();
-            reply.error(err.raw_os_error().unwrap());
+            reply.error(errno(&err.unwrap_err()));
         } else {
             reply.ok();
         }
@@ -279,7 +343,15 @@ impl fuse::Filesystem for Filesystem {
     fn opendir(&mut self, _req: &Request, _ino: u64, _flags: u32, reply: ReplyOpen) {
         /*self.log
         .printInfo(&format!("Filesystem OpenDir INO {} FLAGS {}", _ino, _flags));*/
-        reply.opened(_ino, 0);
+        let listing = self.api.readdirplus(_ino);
+        if listing.is_err() {
+            reply.error(errno(&listing.unwrap_err()));
+            return;
+        }
+        let fh = self.next_dir_handle;
+        self.next_dir_handle += 1;
+        self.dirs.insert(fh, listing.unwrap());
+        reply.opened(fh, 0);
     }
 
     fn readdir(
@@ -293,57 +365,28 @@ impl fuse::Filesystem for Filesystem {
         /*self.log
         .printAction(&format!("Filesystem ReadDir INO {}", _ino));*/
 
-        let mut entries = vec![
-            //(1, FileType::Directory, "."),
-            //(1, FileType::Directory, ".."),
-           // (2, FileType::RegularFile, "hello.txt"),
-        ];
-
-        let readdir_entry = self.api.get_entry_from_inode(_ino);
-        if readdir_entry.is_err() {
-            //reply.error(0);
+        let listing = self.dirs.get(&_fh);
+        if listing.is_none() {
+            reply.error(libc::EBADF);
             return;
         }
-
-        let dir_entries = self.api.readdir(&readdir_entry.unwrap().path);
-        for dir_entr in dir_entries.iter() {
-            let p = Path::new(dir_entr);
-            let comp = p.components().last().expect("");
-            let name = comp.as_os_str().to_str().expect("");
-            let finfo = self.api.get_entry(dir_entr); // FIX
-            if finfo.is_err() {
-                continue;
-            }
-            let finfo = finfo.unwrap();
-            let inode_num = attr(&finfo).ino;
-            let kind = finfo.kind;
-            let file_tup: (u64, FileType, &str) = (inode_num, to_filetype(&kind), &name);
-            /*self.log.printInfo(&format!(
-                "{} {} {}",
-                file_tup.0,
-                if to_filetype(&kind) == FileType::RegularFile {
-                    "File"
-                } else {
-                    "Directory"
-                },
-                file_tup.2
-            ));*/
-            entries.push(file_tup);
-        }
-
-        for (i, entry) in entries.into_iter().enumerate().skip(_offset as usize) {
+        for (i, entry) in listing.unwrap().iter().enumerate().skip(_offset as usize) {
             // i + 1 means the index of the next entry
-            reply.add(entry.0, (i + 1) as i64, entry.1, entry.2);
+            let kind = to_filetype(&entry.entry.kind);
+            if reply.add(attr(&entry.entry).ino, (i + 1) as i64, kind, &entry.name) {
+                // The buffer is full, the kernel asks again starting at the next entry
+                break;
+            }
         }
         reply.ok();
     }
 
     fn releasedir(&mut self, _req: &Request, _ino: u64, _fh: u64, _flags: u32, reply: ReplyEmpty) {
         //self.log.printInfo("Filesystem ReleaseDir");
+        self.dirs.remove(&_fh);
         reply.ok();
     }
 
-    // TODO : Implement fsyncdir
     fn fsyncdir(
         &mut self,
         _req: &Request,
@@ -352,12 +395,16 @@ impl fuse::Filesystem for Filesystem {
         _datasync: bool,
         reply: ReplyEmpty,
     ) {
-        reply.ok();
+        let err = self.api.fsync(_ino, None, _datasync);
+        if err.is_err() {
+            reply.error(errno(&err.unwrap_err()));
+        } else {
+            reply.ok();
+        }
     }
 
     // ETC
 
-    // TODO : Implement link
     fn link(
         &mut self,
         _req: &Request,
@@ -366,13 +413,24 @@ impl fuse::Filesystem for Filesystem {
         _newname: &OsStr,
         reply: ReplyEntry,
     ) {
-        reply.error(0);
+        let entry = self.api.link(_ino, _newparent, _newname);
+        if entry.is_ok() {
+            let entry = entry.unwrap();
+            reply.entry(&Timespec::new(0, 0), &attr(&entry), entry.generation);
+        } else {
+            reply.error(errno(&entry.unwrap_err()));
+        }
     }
 
     fn open(&mut self, _req: &Request, _ino: u64, _flags: u32, reply: ReplyOpen) {
         /*self.log
         .printAction(&format!("Filesystem OPEN INO {}", _ino));*/
-        reply.opened(0, 0);
+        let fh = self.api.open(_ino, _flags as i32);
+        if fh.is_err() {
+            reply.error(errno(&fh.unwrap_err()));
+        } else {
+            reply.opened(fh.unwrap(), 0);
+        }
     }
 
     fn read(
@@ -388,10 +446,10 @@ impl fuse::Filesystem for Filesystem {
             "Filesystem Read INO {} OFFSET {} SIZE {}",
             _ino, _offset, _size
         ));*/
-        let v = self.api.read(_ino, _offset, _size);
+        let v = self.api.read(_ino, handle(_fh), _offset, _size);
         if v.is_err() {
             let err = v.unwrap_err();
-            reply.error(err.raw_os_error().unwrap());
+            reply.error(errno(&err));
         } else {
             let v = v.unwrap();
             reply.data(v.as_slice());
@@ -411,18 +469,32 @@ impl fuse::Filesystem for Filesystem {
         println!("Fuse write");
         /*self.log
         .printAction(&format!("Filesystem Write INO {} OFFSET {}", _ino, _offset));*/
-        let v = self.api.write(_ino, _offset, _data);
+        let v = self.api.write(_ino, handle(_fh), _offset, _data);
         if v.is_err() {
             let err = v.unwrap_err();
-            reply.error(err.raw_os_error().unwrap());
+            reply.error(errno(&err));
         } else {
+            if _fh != 0 {
+                self.written.insert(_fh);
+            }
             reply.written(v.unwrap());
         }
     }
 
     fn flush(&mut self, _req: &Request, _ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
         //self.log.printInfo("Filesystem Flush");
-        reply.ok();
+        // Writes are not buffered, so closing only has to report errors of getting them to disk
+        let mut err = self.flush_written(_ino, _fh);
+        // Closing any descriptor of a file releases the POSIX locks of the process on it
+        let released = self.api.release_locks(_ino, _lock_owner);
+        if err.is_ok() {
+            err = released;
+        }
+        if err.is_err() {
+            reply.error(errno(&err.unwrap_err()));
+        } else {
+            reply.ok();
+        }
     }
 
     fn release(
@@ -436,20 +508,50 @@ impl fuse::Filesystem for Filesystem {
         reply: ReplyEmpty,
     ) {
         //self.log.printInfo("Filesystem Release");
-        reply.ok();
+        if _fh == 0 {
+            reply.ok();
+            return;
+        }
+        let mut err = Ok(());
+        if _flush {
+            err = self.flush_written(_ino, _fh);
+            let released = self.api.release_locks(_ino, _lock_owner);
+            if err.is_ok() {
+                err = released;
+            }
+        }
+        self.written.remove(&_fh);
+        let released = self.api.release(_fh);
+        if err.is_ok() {
+            err = released;
+        }
+        if err.is_err() {
+            reply.error(errno(&err.unwrap_err()));
+        } else {
+            reply.ok();
+        }
     }
 
-    // TODO : Implement fsync
     fn fsync(&mut self, _req: &Request, _ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
-        reply.ok();
+        self.written.remove(&_fh);
+        let err = self.api.fsync(_ino, handle(_fh), _datasync);
+        if err.is_err() {
+            reply.error(errno(&err.unwrap_err()));
+        } else {
+            reply.ok();
+        }
     }
 
-    // TODO : Implement statfs
     fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
-        //self.log.printInfo("Filesystem Statfs");
-        let size = 1000;
-        let used = 20;
-        reply.statfs(size, size - used, size - used, 1, 0, 0, 0, 0);
+        let st = self.api.statfs();
+        if st.is_err() {
+            reply.error(errno(&st.unwrap_err()));
+            return;
+        }
+        let st = st.unwrap();
+        reply.statfs(
+            st.blocks, st.bfree, st.bavail, st.files, st.ffree, st.bsize, st.namelen, st.frsize,
+        );
     }
 
     fn create(
@@ -463,15 +565,15 @@ impl fuse::Filesystem for Filesystem {
     ) {
         /*self.log
         .printAction(&format!("Filesystem Create {}", _name.to_str().unwrap()));*/
-        let dir = self.api.create(_parent, _name.to_str().expect(""));
+        let dir = self.api.create(_parent, _name, _flags as i32, _mode);
         if dir.is_ok() {
-            reply.created(&Timespec::new(0, 0), &attr(&dir.unwrap()), 0, 0, 0);
+            let (entry, fh) = dir.unwrap();
+            reply.created(&Timespec::new(0, 0), &attr(&entry), entry.generation, fh, 0);
         } else {
-            reply.error(dir.unwrap_err().raw_os_error().unwrap());
+            reply.error(errno(&dir.unwrap_err()));
         }
     }
 
-    // TODO : Implement getlk
     fn getlk(
         &mut self,
         _req: &Request,
@@ -484,10 +586,26 @@ impl fuse::Filesystem for Filesystem {
         _pid: u32,
         reply: ReplyLock,
     ) {
-        reply.error(0);
+        let kind = LockKind::from_fcntl(_typ as i32);
+        if kind.is_none() {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        let lock = FileLock {
+            start: _start,
+            end: _end,
+            kind: kind.unwrap(),
+            pid: _pid,
+        };
+        let lock = self.api.getlk(_ino, _lock_owner, lock);
+        if lock.is_err() {
+            reply.error(errno(&lock.unwrap_err()));
+            return;
+        }
+        let lock = lock.unwrap();
+        reply.locked(lock.start, lock.end, lock.kind.to_fcntl() as u32, lock.pid);
     }
 
-    // TODO : Implement setlk
     fn setlk(
         &mut self,
         _req: &Request,
@@ -501,11 +619,39 @@ impl fuse::Filesystem for Filesystem {
         _sleep: bool,
         reply: ReplyEmpty,
     ) {
-        reply.ok();
+        let kind = LockKind::from_fcntl(_typ as i32);
+        if kind.is_none() {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        let lock = FileLock {
+            start: _start,
+            end: _end,
+            kind: kind.unwrap(),
+            pid: _pid,
+        };
+        if _sleep {
+            // Waiting here would stall every other request of the mount
+            self.api.setlk_wait(_ino, _lock_owner, lock, move |err| {
+                if err.is_err() {
+                    reply.error(errno(&err.unwrap_err()));
+                } else {
+                    reply.ok();
+                }
+            });
+            return;
+        }
+        let err = self.api.setlk(_ino, _lock_owner, lock);
+        if err.is_err() {
+            reply.error(errno(&err.unwrap_err()));
+        } else {
+            reply.ok();
+        }
     }
 
     fn bmap(&mut self, _req: &Request, _ino: u64, _blocksize: u32, _idx: u64, reply: ReplyBmap) {
-       reply.error(0);
+        // Blocks of the volume can not be mapped to a device
+        reply.error(libc::ENOSYS);
     }
 
     #[cfg(target_os = "macos")]
@@ -0,0 +1,53 @@
+#![no_main]
+//! Feeds arbitrary frames through [Call](shfs_api::calls::Call) decoding and dispatch of the server,
+//! which has to answer every one of them without panicking.
+//!
+//! Run with `cargo fuzz run dispatch` from the `fuzz` directory.
+//! The served volume is a scratch directory inside the temp directory.
+
+use libfuzzer_sys::fuzz_target;
+use shfs_api::config::ServerConfig;
+use shfs_api::encoding::Encoding;
+use shfs_api::protocol::Capabilities;
+use shfs_server::{FileServer, Session};
+use std::sync::{Arc, OnceLock};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+
+/// Session of the fuzzed requests.
+/// It is never registered with the lock manager, so lock waits fail instead of blocking forever.
+const SESSION: u64 = 1;
+
+fn server() -> &'static (Runtime, Arc<FileServer>) {
+    static SERVER: OnceLock<(Runtime, Arc<FileServer>)> = OnceLock::new();
+    return SERVER.get_or_init(|| {
+        let root = std::env::temp_dir().join(format!("shfs-fuzz-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let config: ServerConfig = serde_json::from_value(serde_json::json!({
+            "volumes": [{ "name": "fuzz", "root": root }]
+        }))
+        .unwrap();
+        // The listener is never accepted on, but needs a runtime to exist
+        let rt = Runtime::new().unwrap();
+        let listener = rt.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let server = Arc::new(FileServer::with_config(config, listener));
+        return (rt, server);
+    });
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    // The first byte picks the encoding, the rest is the frame
+    let encoding = if data[0] & 1 == 0 {
+        Encoding::Cbor
+    } else {
+        Encoding::Json
+    };
+    let mut capabilities = Capabilities::supported();
+    capabilities.encodings = vec![encoding];
+    let session = Arc::new(Session::new(SESSION, capabilities));
+    let (rt, server) = server();
+    let _ = rt.block_on(FileServer::handle_frame(server.clone(), session, data[1..].to_vec()));
+});
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// File opened by a client session
+struct OpenFile {
+    session: u64,
+    vol_id: usize,
+    file: Arc<File>,
+}
+
+/// Files held open by client sessions.
+///
+/// A handle stays valid across renames and unlinks of its file
+/// until it is released or its session disconnects.
+pub struct HandleTable {
+    handles: Mutex<HashMap<u64, OpenFile>>,
+    next_fh: AtomicU64,
+}
+
+impl HandleTable {
+    pub fn new() -> HandleTable {
+        return HandleTable {
+            handles: Mutex::new(HashMap::new()),
+            next_fh: AtomicU64::new(1),
+        };
+    }
+
+    /// Keeps `file` open for `session` returning its handle
+    pub fn insert(&self, session: u64, vol_id: usize, file: File) -> u64 {
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.handles.lock().unwrap().insert(
+            fh,
+            OpenFile {
+                session,
+                vol_id,
+                file: Arc::new(file),
+            },
+        );
+        return fh;
+    }
+
+    /// Returns the file of a handle, failing with EBADF if the handle is not held by `session` on the volume
+    pub fn get(&self, session: u64, vol_id: usize, fh: u64) -> Result<Arc<File>, std::io::Error> {
+        let handles = self.handles.lock().unwrap();
+        return match handles.get(&fh) {
+            Some(f) if f.session == session && f.vol_id == vol_id => Ok(f.file.clone()),
+            _ => Err(std::io::Error::from_raw_os_error(libc::EBADF)),
+        };
+    }
+
+    /// Closes a handle, failing with EBADF if it is not held by `session`
+    pub fn remove(&self, session: u64, fh: u64) -> Result<(), std::io::Error> {
+        let mut handles = self.handles.lock().unwrap();
+        let held = handles.get(&fh).map(|f| f.session == session);
+        if held != Some(true) {
+            return Err(std::io::Error::from_raw_os_error(libc::EBADF));
+        }
+        handles.remove(&fh);
+        return Ok(());
+    }
+
+    /// Closes every handle of a session
+    pub fn close_session(&self, session: u64) {
+        self.handles.lock().unwrap().retain(|_, f| f.session != session);
+    }
+}
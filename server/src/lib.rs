@@ -1,17 +1,84 @@
+mod handles;
+mod locks;
+
+use handles::HandleTable;
+use locks::LockManager;
 use rich::{unpack_or_default, unwrap_or_err};
-use shfs_api::calls::{Call};
+use shfs_api::calls::{Call, Request, RequestId, RequestInfo};
 use shfs_api::config::ServerConfig;
-use shfs_api::responses::Response;
+use shfs_api::encoding::Encoding;
+use shfs_api::error::{ErrorKind, ShfsError};
+use shfs_api::filesystem_entry::{AttrChanges, FsFiletype};
+use shfs_api::framing::{read_frame, write_frame};
+use shfs_api::lock::FileLock;
+use shfs_api::protocol::{Capabilities, COMPRESSION_ZSTD, PROTOCOL_VERSION};
+use shfs_api::responses::{Reply, Response};
 use shfs_api::volume::Volume;
+use shfs_api::xattr::{self, XattrReply};
+use shfs_api::FilesystemAPI;
+use std::ffi::OsStr;
 use std::io::{Read};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinSet;
+
+/// Requests of a session handled at once, counting until their reply is sent.
+/// Further requests are not read before one of them is answered.
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Lock requests of a session waiting at once, which do not count as in flight.
+/// Further ones fail with ENOLCK.
+pub const MAX_LOCK_WAITERS: usize = 256;
+
+/// A connection of a client
+pub struct Session {
+    /// Id the handles and locks of the session are held under
+    pub id: u64,
+    /// Capabilities negotiated in the handshake, optional calls outside of them are refused
+    pub capabilities: Capabilities,
+    /// Permits of the lock requests waiting
+    waiters: Arc<Semaphore>,
+}
+
+impl Session {
+    /// Returns a new [Session] using the negotiated `capabilities`
+    pub fn new(id: u64, capabilities: Capabilities) -> Session {
+        return Session {
+            id,
+            capabilities,
+            waiters: Arc::new(Semaphore::new(MAX_LOCK_WAITERS)),
+        };
+    }
+}
+
+#[derive(Debug)]
+/// Result of handling a [Request] on a blocking task
+enum Handled {
+    /// The [Reply] to send, `None` if the request can not be answered
+    Reply(Option<Reply>),
+    /// A valid lock request which has to wait for conflicting locks to be released
+    WaitLock {
+        id: u64,
+        info: RequestInfo,
+        ino: u64,
+        owner: u64,
+        lock: FileLock,
+    },
+}
 
 /// File Server Object
 pub struct FileServer {
     listener: TcpListener,
     config: ServerConfig,
     volumes: Vec<Volume>,
+    locks: LockManager,
+    handles: HandleTable,
+    next_session: AtomicU64,
 }
 
 impl FileServer {
@@ -27,44 +94,333 @@ impl FileServer {
         let mut buf = vec![];
         unwrap_or_err(conf_file.read_to_end(&mut buf), "Error receiving call");
         let config: ServerConfig = serde_json::from_slice(&buf).expect("");
+        return Ok(FileServer::with_config(config, listener));
+    }
+
+    /// Returns a [FileServer] serving the volumes of `config` on `listener`
+    pub fn with_config(config: ServerConfig, listener: TcpListener) -> FileServer {
         let mut volumes = vec![];
         for vol in &config.volumes {
-            volumes.push(Volume::new(vol));
+            volumes.push(Volume::new(vol, config.state_dir.as_ref()));
         }
-        return Ok(FileServer {
+        return FileServer {
             listener,
             config,
             volumes,
-        });
+            locks: LockManager::new(),
+            handles: HandleTable::new(),
+            next_session: AtomicU64::new(1),
+        };
     }
 
-    async fn handle_cmd(&mut self, mut stream: TcpStream) {
-        let mut buf = [0; 1024];
-        let n = match stream.read(&mut buf).await {
-            Ok(n) if n == 0 => {
-                return;
+    /// Serves a single client connection.
+    ///
+    /// After the handshake every incoming [Request] is handled on its own blocking task,
+    /// [Reply]s are written back in the order they are finished.
+    /// The connection is closed if a reply can not be sent or a request can not be answered at all.
+    /// Locks and file handles held by the session are released once the connection closes
+    /// and every request still in flight is finished.
+    async fn serve_connection(server: Arc<FileServer>, stream: TcpStream) {
+        let (mut reader, mut writer) = stream.into_split();
+
+        let capabilities = FileServer::handshake(&mut reader, &mut writer).await;
+        if capabilities.is_none() {
+            return;
+        }
+        let session = Arc::new(Session::new(
+            server.next_session.fetch_add(1, Ordering::Relaxed),
+            capabilities.unwrap(),
+        ));
+        let encoding = session.capabilities.encoding();
+        let compress = session.capabilities.has_compression(COMPRESSION_ZSTD);
+        server.locks.open_session(session.id);
+
+        // Replies carry the permit of their request, so it is only returned once the reply is sent
+        let (reply_tx, mut reply_rx) = unbounded_channel::<(Option<Reply>, Option<OwnedSemaphorePermit>)>();
+        let writer_task = tokio::spawn(async move {
+            while let Some((reply, _permit)) = reply_rx.recv().await {
+                if reply.is_none() {
+                    eprintln!("Closing connection after malformed request");
+                    break;
+                }
+                let data = FileServer::encode_reply(reply.unwrap(), encoding, compress);
+                let err = write_frame(&mut writer, &data).await;
+                if err.is_err() {
+                    eprintln!("Error sending response: {}", err.unwrap_err());
+                    break;
+                }
             }
-            Ok(n) => n,
+        });
+
+        let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+        let mut tasks = JoinSet::new();
+        loop {
+            let mut permit = tokio::select! {
+                permit = in_flight.clone().acquire_owned() => Some(permit.unwrap()),
+                _ = reply_tx.closed() => break,
+            };
+            let frame = tokio::select! {
+                frame = read_frame(&mut reader) => frame,
+                // The writer stopped, replies to further requests would never arrive
+                _ = reply_tx.closed() => break,
+            };
+            let data = match frame {
+                Ok(Some(data)) => data,
+                Ok(None) => break,
+                Err(e) => {
+                    eprintln!("failed to read from socket; err = {:?}", e);
+                    break;
+                }
+            };
+            // Forgetting requests finished meanwhile
+            while tasks.try_join_next().is_some() {}
+            let server = server.clone();
+            let session = session.clone();
+            let reply_tx = reply_tx.clone();
+            tasks.spawn(async move {
+                let reply = FileServer::handle_in_flight(server, session, data, &mut permit).await;
+                let _ = reply_tx.send((reply, permit));
+            });
+        }
+
+        // Closing the lock session first fails lock requests still waiting,
+        // handles are closed once no request can open another one
+        server.locks.close_session(session.id);
+        while tasks.join_next().await.is_some() {}
+        server.handles.close_session(session.id);
+        drop(reply_tx);
+        let _ = writer_task.await;
+    }
+
+    /// Performs the handshake at the start of a connection.
+    ///
+    /// Returns the negotiated [Capabilities] or `None` if the client was refused.
+    async fn handshake(reader: &mut OwnedReadHalf, writer: &mut OwnedWriteHalf) -> Option<Capabilities> {
+        let data = match read_frame(reader).await {
+            Ok(Some(data)) => data,
+            Ok(None) => return None,
             Err(e) => {
                 eprintln!("failed to read from socket; err = {:?}", e);
-                return;
+                return None;
+            }
+        };
+        let encoding = Encoding::default();
+        let req: Result<Request, std::io::Error> = encoding.decode(&data);
+        if req.is_err() {
+            return None;
+        }
+        let req = req.unwrap();
+
+        let negotiated = match req.call {
+            Call::Handshake {
+                version,
+                capabilities,
+            } => {
+                if version != PROTOCOL_VERSION {
+                    Err(format!(
+                        "Unsupported protocol version {}, server speaks version {}",
+                        version, PROTOCOL_VERSION
+                    ))
+                } else {
+                    Capabilities::supported().negotiate(&capabilities)
+                }
             }
+            _ => Err(String::from("Expected handshake")),
         };
-        let data = &buf[0..n];
-        // TODO : Implement better reading
-        let obj: Call = serde_json::from_slice(&data).expect("What happened?");
+        let response = match &negotiated {
+            Ok(capabilities) => Response::Handshake {
+                version: PROTOCOL_VERSION,
+                capabilities: capabilities.clone(),
+            },
+            Err(error) => Response::Error {
+                error: error.to_string(),
+            },
+        };
+        let reply = FileServer::encode_reply(
+            Reply {
+                id: req.id,
+                response,
+            },
+            encoding,
+            false,
+        );
+        if write_frame(writer, &reply).await.is_err() || negotiated.is_err() {
+            return None;
+        }
+        return negotiated.ok();
+    }
+
+    /// Decodes and handles a single [Request] of `session` returning its [Reply].
+    ///
+    /// The request is handled on a blocking task, but waiting for a lock happens outside of it,
+    /// so waiting clients can not use up the threads needed to release the locks they wait for.
+    /// Malformed requests are answered with an error if at least their id can be decoded.
+    /// Otherwise `None` is returned, as no one could receive the reply and the connection has to be closed.
+    pub async fn handle_frame(server: Arc<FileServer>, session: Arc<Session>, data: Vec<u8>) -> Option<Reply> {
+        return FileServer::handle_in_flight(server, session, data, &mut None).await;
+    }
+
+    /// Handles a frame like [handle_frame](FileServer::handle_frame) holding the `permit` of a session
+    /// to have a request in flight. The permit is returned while waiting for a lock,
+    /// as the lock may only be released by later requests of the session.
+    async fn handle_in_flight(
+        server: Arc<FileServer>,
+        session: Arc<Session>,
+        data: Vec<u8>,
+        permit: &mut Option<OwnedSemaphorePermit>,
+    ) -> Option<Reply> {
+        let srv = server.clone();
+        let sess = session.clone();
+        let handled = tokio::task::spawn_blocking(move || srv.handle_request(&sess, &data)).await;
+        if handled.is_err() {
+            eprintln!("Error handling request: {}", handled.unwrap_err());
+            return None;
+        }
+        return match handled.unwrap() {
+            Handled::Reply(reply) => reply,
+            Handled::WaitLock {
+                id,
+                info,
+                ino,
+                owner,
+                lock,
+            } => {
+                let waiting = session.waiters.clone().try_acquire_owned();
+                if waiting.is_err() {
+                    let err = std::io::Error::from_raw_os_error(libc::ENOLCK);
+                    return Some(Reply {
+                        id,
+                        response: Response::IOError {
+                            error: ShfsError::from(err).with_context("set_lock"),
+                        },
+                    });
+                }
+                permit.take();
+                let data = server
+                    .locks
+                    .wait_lock(info.volume_id as usize, ino, session.id, owner, &lock)
+                    .await;
+                let response = match data {
+                    Ok(()) => Response::Ok {},
+                    Err(e) => Response::IOError {
+                        error: ShfsError::from(e).with_context("set_lock"),
+                    },
+                };
+                Some(Reply { id, response })
+            }
+        };
+    }
+
+    /// Decodes and handles a single [Request] of `session`, see [handle_frame](FileServer::handle_frame)
+    fn handle_request(&self, session: &Session, data: &[u8]) -> Handled {
+        let encoding = session.capabilities.encoding();
+        let req: Result<Request, std::io::Error> = encoding.decode(data);
+        if req.is_err() {
+            let id: Result<RequestId, std::io::Error> = encoding.decode(data);
+            if id.is_err() {
+                return Handled::Reply(None);
+            }
+            return Handled::Reply(Some(Reply {
+                id: id.unwrap().id,
+                response: Response::Error {
+                    error: format!("Malformed call: {}", req.unwrap_err()),
+                },
+            }));
+        }
+        let req = req.unwrap();
+        if let Call::SetLock { sleep: true, .. } = &req.call {
+            // Invalid requests are answered by handle_call
+            if self.validate(session, &req.call).is_ok() {
+                if let Call::SetLock {
+                    info,
+                    ino,
+                    owner,
+                    lock,
+                    ..
+                } = req.call
+                {
+                    return Handled::WaitLock {
+                        id: req.id,
+                        info,
+                        ino,
+                        owner,
+                        lock,
+                    };
+                }
+            }
+        }
+        return Handled::Reply(Some(Reply {
+            id: req.id,
+            response: self.handle_call(session, req.call),
+        }));
+    }
+
+    /// Checks a [Call] of `session` before it is handled,
+    /// including that its volume exists and that optional calls were negotiated
+    fn validate(&self, session: &Session, obj: &Call) -> Result<(), ShfsError> {
+        let optional = obj.optional();
+        if optional.is_some() && !session.capabilities.has_call(optional.unwrap()) {
+            return Err(ShfsError::new(ErrorKind::NotSupported, "Call was not negotiated"));
+        }
+        obj.validate()?;
+        let max = session.capabilities.encoding().max_io_size();
+        match obj {
+            Call::Read { size, .. } if *size > max => {
+                return Err(ShfsError::new(ErrorKind::InvalidInput, "Read exceeds maximum size of the encoding"));
+            }
+            Call::Write { data, .. } if data.len() > max as usize => {
+                return Err(ShfsError::new(ErrorKind::InvalidInput, "Write exceeds maximum size of the encoding"));
+            }
+            _ => {}
+        }
+        let info = obj.info();
+        if info.is_some() && info.unwrap().volume_id >= self.volumes.len() as u64 {
+            return Err(ShfsError::new(ErrorKind::NotFound, "Volume does not exist"));
+        }
+        return Ok(());
+    }
+
+    /// Handles a single [Call] of `session` returning the [Response]
+    fn handle_call(&self, session: &Session, obj: Call) -> Response {
         //println!("{:?}", obj);
 
-        let resp = match obj {
+        let call = obj.name();
+        let valid = self.validate(session, &obj);
+        if valid.is_err() {
+            return Response::IOError {
+                error: valid.unwrap_err().with_context(call),
+            };
+        }
+        let mut resp = match obj {
             Call::ReadDir { info, path } => {
                 let data = self.volumes[info.volume_id as usize].api.readdir(&path);
-                Response::ReadDir { data: data }
+                if data.is_err() {
+                    Response::IOError {
+                        error: data.unwrap_err().into(),
+                    }
+                } else {
+                    Response::ReadDir {
+                        data: data.unwrap(),
+                    }
+                }
+            }
+            Call::ReadDirPlus { info, ino } => {
+                let data = self.volumes[info.volume_id as usize].api.readdirplus(ino);
+                if data.is_err() {
+                    Response::IOError {
+                        error: data.unwrap_err().into(),
+                    }
+                } else {
+                    Response::ReadDirPlus {
+                        data: data.unwrap(),
+                    }
+                }
             }
             Call::GetEntry { info, path } => {
                 let data = self.volumes[info.volume_id as usize].api.get_entry(&path);
                 if data.is_err() {
                     Response::IOError {
-                        error: data.unwrap_err().raw_os_error().unwrap(),
+                        error: data.unwrap_err().into(),
                     }
                 } else {
                     Response::GetEntry {
@@ -78,7 +434,7 @@ impl FileServer {
                     .get_entry_from_inode(ino);
                 if data.is_err() {
                     Response::IOError {
-                        error: data.unwrap_err().raw_os_error().unwrap(),
+                        error: data.unwrap_err().into(),
                     }
                 } else {
                     Response::GetEntry {
@@ -92,7 +448,7 @@ impl FileServer {
                     .get_path_from_inode(ino);
                 if data.is_err() {
                     Response::IOError {
-                        error: data.unwrap_err().raw_os_error().unwrap(),
+                        error: data.unwrap_err().into(),
                     }
                 } else {
                     Response::GetPath {
@@ -103,19 +459,28 @@ impl FileServer {
             Call::Read {
                 info,
                 ino,
+                fh,
                 offset,
                 size,
             } => {
-                let data = self.volumes[info.volume_id as usize]
-                    .api
-                    .read(ino, offset, size);
+                let data = match fh {
+                    Some(fh) => self
+                        .handles
+                        .get(session.id, info.volume_id as usize, fh)
+                        .and_then(|file| FilesystemAPI::read_file(&file, offset, size)),
+                    None => self.volumes[info.volume_id as usize]
+                        .api
+                        .read(ino, offset, size),
+                };
                 if data.is_err() {
                     Response::IOError {
-                        error: data.unwrap_err().raw_os_error().unwrap(),
+                        error: data.unwrap_err().into(),
                     }
                 } else {
+                    let data = data.unwrap();
                     Response::Read {
-                        data: data.unwrap(),
+                        eof: data.len() < size as usize,
+                        data,
                     }
                 }
             }
@@ -136,14 +501,19 @@ impl FileServer {
                         .rename(parent, &name, nparent, &nname);
                     if data.is_err() {
                         Response::IOError {
-                            error: data.unwrap_err().raw_os_error().unwrap(),
+                            error: data.unwrap_err().into(),
                         }
                     } else {
                         Response::Ok {}
                     }
                 }
             }
-            Call::Mkdir { info, parent, name } => {
+            Call::Mkdir {
+                info,
+                parent,
+                name,
+                mode,
+            } => {
                 let ro = self.check_read_only(info.volume_id as usize);
                 if ro.is_err() {
                     let ret = ro.unwrap_err();
@@ -151,10 +521,10 @@ impl FileServer {
                 } else {
                     let data = self.volumes[info.volume_id as usize]
                         .api
-                        .mkdir(parent, &name);
+                        .mkdir(parent, &name, mode);
                     if data.is_err() {
                         Response::IOError {
-                            error: data.unwrap_err().raw_os_error().unwrap(),
+                            error: data.unwrap_err().into(),
                         }
                     } else {
                         Response::GetEntry {
@@ -174,14 +544,20 @@ impl FileServer {
                         .rmdir(parent, &name);
                     if data.is_err() {
                         Response::IOError {
-                            error: data.unwrap_err().raw_os_error().unwrap(),
+                            error: data.unwrap_err().into(),
                         }
                     } else {
                         Response::Ok {}
                     }
                 }
             }
-            Call::Create { info, parent, name } => {
+            Call::Create {
+                info,
+                parent,
+                name,
+                flags,
+                mode,
+            } => {
                 let ro = self.check_read_only(info.volume_id as usize);
                 if ro.is_err() {
                     let ret = ro.unwrap_err();
@@ -189,18 +565,48 @@ impl FileServer {
                 } else {
                     let data = self.volumes[info.volume_id as usize]
                         .api
-                        .create(parent, &name);
+                        .create(parent, &name, flags, mode);
                     if data.is_err() {
                         Response::IOError {
-                            error: data.unwrap_err().raw_os_error().unwrap(),
+                            error: data.unwrap_err().into(),
                         }
                     } else {
-                        Response::GetEntry {
-                            data: data.unwrap(),
+                        let (data, file) = data.unwrap();
+                        let fh = self.handles.insert(session.id, info.volume_id as usize, file);
+                        Response::Created { data, fh }
+                    }
+                }
+            }
+            Call::Open { info, ino, flags } => {
+                let writes = flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0;
+                let ro = self.check_read_only(info.volume_id as usize);
+                if writes && ro.is_err() {
+                    let ret = ro.unwrap_err();
+                    ret
+                } else {
+                    let data = self.volumes[info.volume_id as usize].api.open(ino, flags);
+                    if data.is_err() {
+                        Response::IOError {
+                            error: data.unwrap_err().into(),
                         }
+                    } else {
+                        let fh = self
+                            .handles
+                            .insert(session.id, info.volume_id as usize, data.unwrap());
+                        Response::Open { fh }
                     }
                 }
             }
+            Call::Release { fh, .. } => {
+                let data = self.handles.remove(session.id, fh);
+                if data.is_err() {
+                    Response::IOError {
+                        error: data.unwrap_err().into(),
+                    }
+                } else {
+                    Response::Ok {}
+                }
+            }
             Call::Unlink { info, parent, name } => {
                 let ro = self.check_read_only(info.volume_id as usize);
                 if ro.is_err() {
@@ -212,7 +618,7 @@ impl FileServer {
                         .unlink(parent, &name);
                     if data.is_err() {
                         Response::IOError {
-                            error: data.unwrap_err().raw_os_error().unwrap(),
+                            error: data.unwrap_err().into(),
                         }
                     } else {
                         Response::Ok {}
@@ -222,6 +628,7 @@ impl FileServer {
             Call::Write {
                 info,
                 ino,
+                fh,
                 offset,
                 data,
             } => {
@@ -230,20 +637,315 @@ impl FileServer {
                     let ret = ro.unwrap_err();
                     ret
                 } else {
+                    let data = match fh {
+                        Some(fh) => self
+                            .handles
+                            .get(session.id, info.volume_id as usize, fh)
+                            .and_then(|file| FilesystemAPI::write_file(&file, offset, &data)),
+                        None => self.volumes[info.volume_id as usize]
+                            .api
+                            .write(ino, offset, &data),
+                    };
+                    if data.is_err() {
+                        Response::IOError {
+                            error: data.unwrap_err().into(),
+                        }
+                    } else {
+                        Response::Write {
+                            data: data.unwrap(),
+                        }
+                    }
+                }
+            }
+            Call::SetAttr {
+                info,
+                ino,
+                mode,
+                uid,
+                gid,
+                size,
+                atime,
+                mtime,
+            } => {
+                let ro = self.check_read_only(info.volume_id as usize);
+                if ro.is_err() {
+                    let ret = ro.unwrap_err();
+                    ret
+                } else {
+                    let changes = AttrChanges {
+                        mode,
+                        uid,
+                        gid,
+                        size,
+                        atime,
+                        mtime,
+                    };
                     let data = self.volumes[info.volume_id as usize]
                         .api
-                        .write(ino, offset, &data);
+                        .setattr(ino, &changes);
                     if data.is_err() {
                         Response::IOError {
-                            error: data.unwrap_err().raw_os_error().unwrap(),
+                            error: data.unwrap_err().into(),
                         }
                     } else {
-                        Response::Write {
+                        Response::GetEntry {
+                            data: data.unwrap(),
+                        }
+                    }
+                }
+            }
+            Call::Symlink {
+                info,
+                parent,
+                name,
+                target,
+            } => {
+                let ro = self.check_read_only(info.volume_id as usize);
+                if ro.is_err() {
+                    let ret = ro.unwrap_err();
+                    ret
+                } else {
+                    let data = self.volumes[info.volume_id as usize]
+                        .api
+                        .symlink(parent, &name, &target);
+                    if data.is_err() {
+                        Response::IOError {
+                            error: data.unwrap_err().into(),
+                        }
+                    } else {
+                        Response::GetEntry {
+                            data: data.unwrap(),
+                        }
+                    }
+                }
+            }
+            Call::ReadLink { info, ino } => {
+                let data = self.volumes[info.volume_id as usize].api.readlink(ino);
+                if data.is_err() {
+                    Response::IOError {
+                        error: data.unwrap_err().into(),
+                    }
+                } else {
+                    Response::ReadLink {
+                        data: data.unwrap(),
+                    }
+                }
+            }
+            Call::Fsync {
+                info,
+                ino,
+                fh,
+                datasync,
+            } => {
+                let data = match fh {
+                    Some(fh) => self
+                        .handles
+                        .get(session.id, info.volume_id as usize, fh)
+                        .and_then(|file| FilesystemAPI::fsync_file(&file, datasync)),
+                    None => self.volumes[info.volume_id as usize]
+                        .api
+                        .fsync(ino, datasync),
+                };
+                if data.is_err() {
+                    Response::IOError {
+                        error: data.unwrap_err().into(),
+                    }
+                } else {
+                    Response::Ok {}
+                }
+            }
+            Call::StatFs { info } => {
+                let vol = &self.volumes[info.volume_id as usize];
+                let data = vol.api.statfs().and_then(|data| {
+                    if vol.config.quota.is_none() {
+                        return Ok(data);
+                    }
+                    return Ok(data.with_quota(vol.config.quota.unwrap(), vol.api.cached_usage()?));
+                });
+                if data.is_err() {
+                    Response::IOError {
+                        error: data.unwrap_err().into(),
+                    }
+                } else {
+                    Response::StatFs { data: data.unwrap() }
+                }
+            }
+            Call::GetLock {
+                info,
+                ino,
+                owner,
+                lock,
+            } => {
+                let data = self
+                    .locks
+                    .get_lock(info.volume_id as usize, ino, session.id, owner, &lock);
+                Response::Lock { data }
+            }
+            // Requests waiting for the lock are handled by handle_frame
+            Call::SetLock {
+                info,
+                ino,
+                owner,
+                lock,
+                ..
+            } => {
+                let data = self
+                    .locks
+                    .set_lock(info.volume_id as usize, ino, session.id, owner, &lock);
+                if data.is_err() {
+                    Response::IOError {
+                        error: data.unwrap_err().into(),
+                    }
+                } else {
+                    Response::Ok {}
+                }
+            }
+            Call::Mknod {
+                info,
+                parent,
+                name,
+                mode,
+                rdev,
+            } => {
+                let ro = self.check_read_only(info.volume_id as usize);
+                let dev = self.check_device_node(info.volume_id as usize, mode);
+                if ro.is_err() {
+                    let ret = ro.unwrap_err();
+                    ret
+                } else if dev.is_err() {
+                    let ret = dev.unwrap_err();
+                    ret
+                } else {
+                    let data = self.volumes[info.volume_id as usize]
+                        .api
+                        .mknod(parent, &name, mode, rdev);
+                    if data.is_err() {
+                        Response::IOError {
+                            error: data.unwrap_err().into(),
+                        }
+                    } else {
+                        Response::GetEntry {
                             data: data.unwrap(),
                         }
                     }
                 }
             }
+            Call::Link {
+                info,
+                ino,
+                nparent,
+                nname,
+            } => {
+                let ro = self.check_read_only(info.volume_id as usize);
+                if ro.is_err() {
+                    let ret = ro.unwrap_err();
+                    ret
+                } else {
+                    let data = self.volumes[info.volume_id as usize]
+                        .api
+                        .link(ino, nparent, &nname);
+                    if data.is_err() {
+                        Response::IOError {
+                            error: data.unwrap_err().into(),
+                        }
+                    } else {
+                        Response::GetEntry {
+                            data: data.unwrap(),
+                        }
+                    }
+                }
+            }
+            Call::GetXattr {
+                info,
+                ino,
+                name,
+                size,
+            } => {
+                let xa = self.check_xattr(info.volume_id as usize, Some(name.as_os_str()), ErrorKind::NoData);
+                if xa.is_err() {
+                    let ret = xa.unwrap_err();
+                    ret
+                } else {
+                    let data = self.volumes[info.volume_id as usize]
+                        .api
+                        .getxattr(ino, &name)
+                        .and_then(|data| XattrReply::for_size(data, size));
+                    FileServer::xattr_response(data)
+                }
+            }
+            Call::ListXattr { info, ino, size } => {
+                let xa = self.check_xattr(info.volume_id as usize, None, ErrorKind::NotSupported);
+                if xa.is_err() {
+                    let ret = xa.unwrap_err();
+                    ret
+                } else {
+                    let vol = &self.volumes[info.volume_id as usize];
+                    let data = vol.api.listxattr(ino).and_then(|names| {
+                        let mut list = vec![];
+                        for name in names {
+                            if vol.config.xattr_namespaces.is_some()
+                                && !xattr::in_namespaces(&name, vol.config.xattr_namespaces.as_ref().unwrap())
+                            {
+                                continue;
+                            }
+                            list.extend_from_slice(&name);
+                            list.push(0);
+                        }
+                        XattrReply::for_size(list, size)
+                    });
+                    FileServer::xattr_response(data)
+                }
+            }
+            Call::SetXattr {
+                info,
+                ino,
+                name,
+                value,
+                flags,
+            } => {
+                let ro = self.check_read_only(info.volume_id as usize);
+                let xa = self.check_xattr(info.volume_id as usize, Some(name.as_os_str()), ErrorKind::NotSupported);
+                if ro.is_err() {
+                    let ret = ro.unwrap_err();
+                    ret
+                } else if xa.is_err() {
+                    let ret = xa.unwrap_err();
+                    ret
+                } else {
+                    let data = self.volumes[info.volume_id as usize]
+                        .api
+                        .setxattr(ino, &name, &value, flags);
+                    if data.is_err() {
+                        Response::IOError {
+                            error: data.unwrap_err().into(),
+                        }
+                    } else {
+                        Response::Ok {}
+                    }
+                }
+            }
+            Call::RemoveXattr { info, ino, name } => {
+                let ro = self.check_read_only(info.volume_id as usize);
+                let xa = self.check_xattr(info.volume_id as usize, Some(name.as_os_str()), ErrorKind::NoData);
+                if ro.is_err() {
+                    let ret = ro.unwrap_err();
+                    ret
+                } else if xa.is_err() {
+                    let ret = xa.unwrap_err();
+                    ret
+                } else {
+                    let data = self.volumes[info.volume_id as usize]
+                        .api
+                        .removexattr(ino, &name);
+                    if data.is_err() {
+                        Response::IOError {
+                            error: data.unwrap_err().into(),
+                        }
+                    } else {
+                        Response::Ok {}
+                    }
+                }
+            }
             Call::ListVolumes {} => {
                 let mut ret = vec![];
                 for volume in &self.volumes {
@@ -275,12 +977,29 @@ impl FileServer {
                 Response::ServerInfo {
                     name: name.to_string(),
                     version: option_env!("CARGO_PKG_VERSION").unwrap().to_string(),
+                    protocol: PROTOCOL_VERSION,
                 }
             }
+            Call::Handshake { .. } => Response::Error {
+                error: String::from("Handshake already done"),
+            },
             //_ => Response::invalid,
         };
 
-        let mut resp = serde_json::to_vec(&resp).unwrap();
+        if let Response::IOError { error } = &mut resp {
+            if error.context.is_none() {
+                error.context = Some(call.to_string());
+            }
+        }
+        return resp;
+    }
+
+    /// Serializes a [Reply], compressing the contained [Response] if it gets smaller
+    fn encode_reply(reply: Reply, encoding: Encoding, compress: bool) -> Vec<u8> {
+        if !compress {
+            return encoding.encode(&reply).unwrap();
+        }
+        let resp = encoding.encode(&reply.response).unwrap();
 
         let resp_comp = zstd::stream::encode_all(&resp[0..resp.len()], 5).unwrap();
 
@@ -293,11 +1012,20 @@ impl FileServer {
         );*/
 
         if size_comp < size_resp {
-            let obj = Response::Compressed { data: resp_comp };
-            resp = serde_json::to_vec(&obj).unwrap();
+            let obj = Reply {
+                id: reply.id,
+                response: Response::Compressed { data: resp_comp },
+            };
+            let compressed = encoding.encode(&obj).unwrap();
+            // JSON spells the compressed bytes as numbers, so only the encoded replies can be compared
+            let plain = encoding.encode(&reply).unwrap();
+            if compressed.len() < plain.len() {
+                return compressed;
+            }
+            return plain;
         }
 
-        unwrap_or_err(stream.write_all(&resp).await, "Error sending response");
+        return encoding.encode(&reply).unwrap();
     }
 
     /// Checks if the volume is read only.
@@ -306,17 +1034,75 @@ impl FileServer {
     /// * `vol_id` - The ID of the [Volume]
     pub fn check_read_only(&self, vol_id: usize) -> Result<(), Response> {
         if unpack_or_default(self.volumes[vol_id].config.readonly, false) {
-            return Err(Response::IOError { error: 30 });
+            return Err(Response::IOError {
+                error: ShfsError::new(ErrorKind::ReadOnly, "Volume is read only"),
+            });
         }
         return Ok(());
     }
 
-    /// Infinite loop to run the server
-    pub async fn run(&mut self) -> Result<(), std::io::Error> {
-        loop {
-            let (socket, _) = self.listener.accept().await?;
+    /// Checks if a node with `mode` may be created on the volume.
+    /// Device nodes are only allowed if the [Volume] enables them.
+    /// # Arguments
+    /// * `vol_id` - The ID of the [Volume]
+    /// * `mode` - File type and permissions of the node
+    pub fn check_device_node(&self, vol_id: usize, mode: u32) -> Result<(), Response> {
+        if FsFiletype::from_mode(mode).is_device()
+            && !unpack_or_default(self.volumes[vol_id].config.allow_devices, false)
+        {
+            return Err(Response::IOError {
+                error: ShfsError::new(ErrorKind::NotPermitted, "Device nodes are not allowed on this volume"),
+            });
+        }
+        return Ok(());
+    }
 
-            self.handle_cmd(socket).await;
+    /// Checks if Extended Attributes are accessible on the volume.
+    /// Disabled Extended Attributes fail with ENOTSUP,
+    /// names outside the configured namespaces fail with `filtered`.
+    /// # Arguments
+    /// * `vol_id` - The ID of the [Volume]
+    /// * `name` - Name of the Extended Attribute if the call accesses a single one
+    /// * `filtered` - Error returned for filtered names
+    pub fn check_xattr(
+        &self,
+        vol_id: usize,
+        name: Option<&OsStr>,
+        filtered: ErrorKind,
+    ) -> Result<(), Response> {
+        let config = &self.volumes[vol_id].config;
+        if !unpack_or_default(config.xattrs, true) {
+            return Err(Response::IOError {
+                error: ShfsError::new(ErrorKind::NotSupported, "Extended attributes are disabled on this volume"),
+            });
+        }
+        if name.is_some() && config.xattr_namespaces.is_some() {
+            let namespaces = config.xattr_namespaces.as_ref().unwrap();
+            if !xattr::in_namespaces(name.unwrap().as_bytes(), namespaces) {
+                return Err(Response::IOError {
+                    error: ShfsError::new(filtered, "Extended attribute namespace is not accessible"),
+                });
+            }
+        }
+        return Ok(());
+    }
+
+    /// Converts the result of an Extended Attribute read into a [Response]
+    fn xattr_response(data: Result<XattrReply, std::io::Error>) -> Response {
+        return match data {
+            Ok(XattrReply::Size(size)) => Response::XattrSize { size },
+            Ok(XattrReply::Data(data)) => Response::Xattr { data },
+            Err(err) => Response::IOError { error: err.into() },
+        };
+    }
+
+    /// Infinite loop to run the server.
+    /// Each connection is served on its own task.
+    pub async fn run(self) -> Result<(), std::io::Error> {
+        let server = Arc::new(self);
+        loop {
+            let (socket, _) = server.listener.accept().await?;
+            tokio::spawn(FileServer::serve_connection(server.clone(), socket));
         }
     }
 }
@@ -0,0 +1,414 @@
+use shfs_api::lock::{FileLock, LockKind};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// Owner of a lock: the session of a client and the lock owner within it
+type Owner = (u64, u64);
+
+/// Locks are kept per volume and inode
+type LockKey = (usize, u64);
+
+#[derive(Debug, Clone)]
+struct HeldLock {
+    owner: Owner,
+    start: u64,
+    end: u64,
+    kind: LockKind,
+    pid: u32,
+}
+
+impl HeldLock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        return self.start <= end && start <= self.end;
+    }
+
+    /// Returns true if the lock prevents `owner` from taking a lock of `kind` on the range
+    fn conflicts(&self, owner: Owner, start: u64, end: u64, kind: LockKind) -> bool {
+        return self.owner != owner
+            && self.overlaps(start, end)
+            && (kind == LockKind::Write || self.kind == LockKind::Write);
+    }
+}
+
+struct LockState {
+    locks: HashMap<LockKey, Vec<HeldLock>>,
+    /// Sessions currently connected
+    sessions: HashSet<u64>,
+    /// Owners waiting for a lock and the owner they are waiting on
+    waiting: HashMap<Owner, Owner>,
+}
+
+impl LockState {
+    /// Returns the first lock conflicting with the requested one
+    fn conflict(&self, key: LockKey, owner: Owner, start: u64, end: u64, kind: LockKind) -> Option<&HeldLock> {
+        let locks = self.locks.get(&key)?;
+        return locks.iter().find(|l| l.conflicts(owner, start, end, kind));
+    }
+
+    /// Returns true if `owner` waiting on `blocker` closes a cycle of waiting owners
+    fn would_deadlock(&self, owner: Owner, blocker: Owner) -> bool {
+        let mut current = blocker;
+        for _ in 0..=self.waiting.len() {
+            if current == owner {
+                return true;
+            }
+            match self.waiting.get(&current) {
+                Some(next) => current = *next,
+                None => return false,
+            }
+        }
+        return false;
+    }
+
+    /// Releases the range of every lock of `owner`, splitting locks extending past it
+    fn unlock(&mut self, key: LockKey, owner: Owner, start: u64, end: u64) {
+        let locks = self.locks.remove(&key);
+        if locks.is_none() {
+            return;
+        }
+        let mut kept = vec![];
+        for l in locks.unwrap() {
+            if l.owner != owner || !l.overlaps(start, end) {
+                kept.push(l);
+                continue;
+            }
+            if l.start < start {
+                let mut head = l.clone();
+                head.end = start - 1;
+                kept.push(head);
+            }
+            if l.end > end {
+                let mut tail = l;
+                tail.start = end + 1;
+                kept.push(tail);
+            }
+        }
+        if !kept.is_empty() {
+            self.locks.insert(key, kept);
+        }
+    }
+
+    /// Replaces the range of the locks of `owner` with a new lock,
+    /// merging it with adjacent locks of the same kind
+    fn lock(&mut self, key: LockKey, owner: Owner, lock: &FileLock) {
+        self.unlock(key, owner, lock.start, lock.end);
+        let locks = self.locks.entry(key).or_default();
+        let mut new = HeldLock {
+            owner,
+            start: lock.start,
+            end: lock.end,
+            kind: lock.kind,
+            pid: lock.pid,
+        };
+        locks.retain(|l| {
+            if l.owner != owner || l.kind != new.kind {
+                return true;
+            }
+            if l.end.checked_add(1) == Some(new.start) {
+                new.start = l.start;
+                return false;
+            }
+            if new.end.checked_add(1) == Some(l.start) {
+                new.end = l.end;
+                return false;
+            }
+            return true;
+        });
+        locks.push(new);
+    }
+}
+
+/// Manager of the POSIX advisory locks held by clients.
+///
+/// Locks follow fcntl semantics: they are owned by a lock owner of a client session,
+/// a new lock replaces the range of the locks of its owner
+/// and every lock of a session is released once it disconnects.
+pub struct LockManager {
+    state: Mutex<LockState>,
+    /// Wakes the waiting owners whenever locks are released or sessions close
+    changed: Notify,
+}
+
+impl LockManager {
+    pub fn new() -> LockManager {
+        return LockManager {
+            state: Mutex::new(LockState {
+                locks: HashMap::new(),
+                sessions: HashSet::new(),
+                waiting: HashMap::new(),
+            }),
+            changed: Notify::new(),
+        };
+    }
+
+    /// Registers a connected session allowing it to take locks
+    pub fn open_session(&self, session: u64) {
+        self.state.lock().unwrap().sessions.insert(session);
+    }
+
+    /// Releases every lock of a session and fails its pending waits
+    pub fn close_session(&self, session: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.sessions.remove(&session);
+        for locks in state.locks.values_mut() {
+            locks.retain(|l| l.owner.0 != session);
+        }
+        state.locks.retain(|_, locks| !locks.is_empty());
+        self.changed.notify_waiters();
+    }
+
+    /// Returns the first lock conflicting with `lock`.
+    /// If there is none the requested range is returned with [LockKind::Unlock].
+    pub fn get_lock(&self, vol_id: usize, ino: u64, session: u64, owner: u64, lock: &FileLock) -> FileLock {
+        let state = self.state.lock().unwrap();
+        let conflict = state.conflict((vol_id, ino), (session, owner), lock.start, lock.end, lock.kind);
+        return match conflict {
+            Some(l) => FileLock {
+                start: l.start,
+                end: l.end,
+                kind: l.kind,
+                pid: l.pid,
+            },
+            None => FileLock {
+                start: lock.start,
+                end: lock.end,
+                kind: LockKind::Unlock,
+                pid: 0,
+            },
+        };
+    }
+
+    /// Takes or releases a lock without waiting.
+    /// If a conflicting lock is held this fails with EAGAIN.
+    pub fn set_lock(
+        &self,
+        vol_id: usize,
+        ino: u64,
+        session: u64,
+        owner: u64,
+        lock: &FileLock,
+    ) -> Result<(), std::io::Error> {
+        let mut state = self.state.lock().unwrap();
+        let blocker = self.try_lock(&mut state, (vol_id, ino), (session, owner), lock)?;
+        if blocker.is_some() {
+            return Err(std::io::Error::from_raw_os_error(libc::EAGAIN));
+        }
+        return Ok(());
+    }
+
+    /// Takes or releases a lock, waiting for conflicting locks to be released.
+    ///
+    /// Waiting does not occupy a thread, so any number of owners can wait at once.
+    /// Fails with EDEADLK if waiting would deadlock and with EINTR once the session disconnects.
+    pub async fn wait_lock(
+        &self,
+        vol_id: usize,
+        ino: u64,
+        session: u64,
+        owner: u64,
+        lock: &FileLock,
+    ) -> Result<(), std::io::Error> {
+        let key = (vol_id, ino);
+        let owner = (session, owner);
+        loop {
+            // Created before checking, so a release in between is not missed
+            let changed = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                let blocker = self.try_lock(&mut state, key, owner, lock)?;
+                if blocker.is_none() {
+                    return Ok(());
+                }
+                let blocker = blocker.unwrap();
+                if state.would_deadlock(owner, blocker) {
+                    state.waiting.remove(&owner);
+                    return Err(std::io::Error::from_raw_os_error(libc::EDEADLK));
+                }
+                state.waiting.insert(owner, blocker);
+            }
+            changed.await;
+        }
+    }
+
+    /// Takes or releases a lock unless a conflicting lock is held,
+    /// in which case the owner of the conflicting lock is returned
+    fn try_lock(
+        &self,
+        state: &mut LockState,
+        key: LockKey,
+        owner: Owner,
+        lock: &FileLock,
+    ) -> Result<Option<Owner>, std::io::Error> {
+        if !state.sessions.contains(&owner.0) {
+            state.waiting.remove(&owner);
+            return Err(std::io::Error::from_raw_os_error(libc::EINTR));
+        }
+        if lock.kind == LockKind::Unlock {
+            state.unlock(key, owner, lock.start, lock.end);
+            self.changed.notify_waiters();
+            return Ok(None);
+        }
+        let blocker = state
+            .conflict(key, owner, lock.start, lock.end, lock.kind)
+            .map(|l| l.owner);
+        if blocker.is_some() {
+            return Ok(blocker);
+        }
+        state.waiting.remove(&owner);
+        state.lock(key, owner, lock);
+        // Downgrading a lock may unblock readers
+        self.changed.notify_waiters();
+        return Ok(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn lock(start: u64, end: u64, kind: LockKind) -> FileLock {
+        return FileLock {
+            start,
+            end,
+            kind,
+            pid: 1,
+        };
+    }
+
+    /// Returns the ranges and kinds held on inode 1 of volume 0, ordered by start
+    fn held(locks: &LockManager) -> Vec<(u64, u64, u64, LockKind)> {
+        let state = locks.state.lock().unwrap();
+        let mut ret: Vec<_> = state
+            .locks
+            .get(&(0, 1))
+            .map(|l| l.iter().map(|l| (l.owner.0, l.start, l.end, l.kind)).collect())
+            .unwrap_or_default();
+        ret.sort_by_key(|l| (l.1, l.0));
+        return ret;
+    }
+
+    fn manager(sessions: &[u64]) -> LockManager {
+        let locks = LockManager::new();
+        for s in sessions {
+            locks.open_session(*s);
+        }
+        return locks;
+    }
+
+    #[test]
+    fn unlock_splits_ranges() {
+        let locks = manager(&[1]);
+        locks.set_lock(0, 1, 1, 0, &lock(0, 99, LockKind::Write)).unwrap();
+        locks.set_lock(0, 1, 1, 0, &lock(40, 59, LockKind::Unlock)).unwrap();
+        assert_eq!(held(&locks), vec![(1, 0, 39, LockKind::Write), (1, 60, 99, LockKind::Write)]);
+
+        // A lock of another kind in the middle splits as well
+        locks.set_lock(0, 1, 1, 0, &lock(10, 19, LockKind::Read)).unwrap();
+        assert_eq!(
+            held(&locks),
+            vec![
+                (1, 0, 9, LockKind::Write),
+                (1, 10, 19, LockKind::Read),
+                (1, 20, 39, LockKind::Write),
+                (1, 60, 99, LockKind::Write)
+            ]
+        );
+    }
+
+    #[test]
+    fn adjacent_ranges_merge() {
+        let locks = manager(&[1]);
+        locks.set_lock(0, 1, 1, 0, &lock(0, 9, LockKind::Read)).unwrap();
+        locks.set_lock(0, 1, 1, 0, &lock(20, 29, LockKind::Read)).unwrap();
+        locks.set_lock(0, 1, 1, 0, &lock(10, 19, LockKind::Read)).unwrap();
+        assert_eq!(held(&locks), vec![(1, 0, 29, LockKind::Read)]);
+
+        // Up to the end of the file
+        locks.set_lock(0, 1, 1, 0, &lock(30, u64::MAX, LockKind::Read)).unwrap();
+        assert_eq!(held(&locks), vec![(1, 0, u64::MAX, LockKind::Read)]);
+    }
+
+    #[test]
+    fn conflicts_across_sessions() {
+        let locks = manager(&[1, 2]);
+        locks.set_lock(0, 1, 1, 0, &lock(0, 9, LockKind::Read)).unwrap();
+        // Readers share, also across sessions
+        locks.set_lock(0, 1, 2, 0, &lock(5, 14, LockKind::Read)).unwrap();
+
+        let err = locks.set_lock(0, 1, 2, 0, &lock(0, 4, LockKind::Write)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+        let conflict = locks.get_lock(0, 1, 2, 0, &lock(0, 4, LockKind::Write));
+        assert_eq!((conflict.start, conflict.end, conflict.kind), (0, 9, LockKind::Read));
+
+        // Other owners in the same session conflict as well, other inodes do not
+        let err = locks.set_lock(0, 1, 1, 7, &lock(0, 4, LockKind::Write)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+        locks.set_lock(0, 2, 2, 0, &lock(0, 4, LockKind::Write)).unwrap();
+
+        // Every lock of a session is released once it closes
+        locks.close_session(1);
+        locks.set_lock(0, 1, 2, 0, &lock(0, 4, LockKind::Write)).unwrap();
+        assert!(locks.set_lock(0, 1, 1, 0, &lock(50, 59, LockKind::Read)).is_err());
+    }
+
+    #[tokio::test]
+    async fn waiting_would_deadlock() {
+        let locks = Arc::new(manager(&[1, 2]));
+        locks.set_lock(0, 1, 1, 0, &lock(0, 9, LockKind::Write)).unwrap();
+        locks.set_lock(0, 2, 2, 0, &lock(0, 9, LockKind::Write)).unwrap();
+
+        // Session 1 waits for the lock of session 2
+        let waiter = locks.clone();
+        let waiting = tokio::spawn(async move {
+            return waiter.wait_lock(0, 2, 1, 0, &lock(0, 9, LockKind::Write)).await;
+        });
+        while !locks.state.lock().unwrap().waiting.contains_key(&(1, 0)) {
+            tokio::task::yield_now().await;
+        }
+
+        // Session 2 waiting for session 1 closes the cycle
+        let err = locks.wait_lock(0, 1, 2, 0, &lock(0, 9, LockKind::Write)).await.unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EDEADLK));
+
+        locks.set_lock(0, 2, 2, 0, &lock(0, 9, LockKind::Unlock)).unwrap();
+        waiting.await.unwrap().unwrap();
+        assert!(locks.state.lock().unwrap().waiting.is_empty());
+    }
+
+    #[tokio::test]
+    async fn waiters_do_not_occupy_threads() {
+        // More waiters than the blocking pool has threads, all on a single thread
+        let locks = Arc::new(manager(&[1, 2]));
+        locks.set_lock(0, 1, 1, 0, &lock(0, 0, LockKind::Write)).unwrap();
+        let mut waiting = vec![];
+        for owner in 0..1000 {
+            let waiter = locks.clone();
+            waiting.push(tokio::spawn(async move {
+                return waiter.wait_lock(0, 1, 2, owner, &lock(0, 0, LockKind::Read)).await;
+            }));
+        }
+        tokio::task::yield_now().await;
+        locks.set_lock(0, 1, 1, 0, &lock(0, 0, LockKind::Unlock)).unwrap();
+        for w in waiting {
+            w.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn closing_the_session_interrupts_waiters() {
+        let locks = Arc::new(manager(&[1, 2]));
+        locks.set_lock(0, 1, 1, 0, &lock(0, 9, LockKind::Write)).unwrap();
+        let waiter = locks.clone();
+        let waiting = tokio::spawn(async move {
+            return waiter.wait_lock(0, 1, 2, 0, &lock(0, 9, LockKind::Read)).await;
+        });
+        while locks.state.lock().unwrap().waiting.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        locks.close_session(2);
+        let err = waiting.await.unwrap().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINTR));
+    }
+}
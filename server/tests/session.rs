@@ -0,0 +1,141 @@
+use shfs_api::calls::{Call, Request, RequestInfo};
+use shfs_api::config::ServerConfig;
+use shfs_api::encoding::Encoding;
+use shfs_api::error::ErrorKind;
+use shfs_api::framing::{read_frame, write_frame};
+use shfs_api::lock::{FileLock, LockKind};
+use shfs_api::protocol::{Capabilities, PROTOCOL_VERSION};
+use shfs_api::responses::{Reply, Response};
+use shfs_server::{FileServer, MAX_LOCK_WAITERS};
+use std::path::PathBuf;
+use tempfile::TempDir;
+use tokio::net::{TcpListener, TcpStream};
+
+const INFO: RequestInfo = RequestInfo { volume_id: 0 };
+
+/// Starts a server exporting a temporary directory containing `file` as the volume `v1`
+async fn server() -> (TempDir, String) {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("file"), b"content").unwrap();
+    let config = serde_json::json!({
+        "name": "Test",
+        "volumes": [{ "name": "v1", "root": dir.path().to_str().unwrap() }],
+    });
+    let config: ServerConfig = serde_json::from_value(config).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(FileServer::with_config(config, listener).run());
+    return (dir, addr);
+}
+
+async fn send(stream: &mut TcpStream, id: u64, call: Call) {
+    let data = Encoding::Json.encode(&Request { id, call }).unwrap();
+    write_frame(stream, &data).await.unwrap();
+}
+
+async fn receive(stream: &mut TcpStream) -> Reply {
+    return Encoding::Json.decode(&read_frame(stream).await.unwrap().unwrap()).unwrap();
+}
+
+/// Connects asking for the optional `calls` only
+async fn connect(addr: &str, calls: &[&str]) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let capabilities = Capabilities {
+        compression: vec![],
+        encodings: vec![Encoding::Json],
+        auth: vec!["none".to_string()],
+        calls: calls.iter().map(|c| c.to_string()).collect(),
+    };
+    send(&mut stream, 0, Call::Handshake { version: PROTOCOL_VERSION, capabilities }).await;
+    receive(&mut stream).await;
+    return stream;
+}
+
+async fn ino(stream: &mut TcpStream) -> u64 {
+    send(stream, 1, Call::GetEntry { info: INFO, path: PathBuf::from("/file") }).await;
+    return match receive(stream).await.response {
+        Response::GetEntry { data } => data.ino,
+        r => panic!("{:?}", r),
+    };
+}
+
+fn set_lock(ino: u64, owner: u64, kind: LockKind, sleep: bool) -> Call {
+    let lock = FileLock {
+        start: 0,
+        end: 10,
+        kind,
+        pid: 1,
+    };
+    return Call::SetLock {
+        info: INFO,
+        ino,
+        owner,
+        lock,
+        sleep,
+    };
+}
+
+#[tokio::test]
+async fn calls_which_were_not_negotiated_are_refused() {
+    let (_dir, addr) = server().await;
+    let mut stream = connect(&addr, &["readdirplus"]).await;
+    let ino = ino(&mut stream).await;
+
+    send(&mut stream, 2, set_lock(ino, 1, LockKind::Write, false)).await;
+    match receive(&mut stream).await.response {
+        Response::IOError { error } => assert_eq!(error.kind, ErrorKind::NotSupported),
+        r => panic!("{:?}", r),
+    }
+    send(&mut stream, 3, Call::ReadDirPlus { info: INFO, ino: 1 }).await;
+    assert!(matches!(receive(&mut stream).await.response, Response::ReadDirPlus { .. }));
+}
+
+#[tokio::test]
+async fn lock_waiters_of_a_session_are_limited() {
+    let (_dir, addr) = server().await;
+    let mut stream = connect(&addr, &["locks"]).await;
+    let ino = ino(&mut stream).await;
+    send(&mut stream, 2, set_lock(ino, 1, LockKind::Write, false)).await;
+    assert!(matches!(receive(&mut stream).await.response, Response::Ok {}));
+
+    let extra = 8;
+    for i in 0..(MAX_LOCK_WAITERS + extra) as u64 {
+        send(&mut stream, 100 + i, set_lock(ino, 100 + i, LockKind::Read, true)).await;
+    }
+    // Waiters beyond the limit are answered right away
+    for _ in 0..extra {
+        match receive(&mut stream).await.response {
+            Response::IOError { error } => assert_eq!(error.local_errno(), libc::ENOLCK),
+            r => panic!("{:?}", r),
+        }
+    }
+    send(&mut stream, 3, set_lock(ino, 1, LockKind::Unlock, false)).await;
+    let mut granted = 0;
+    for _ in 0..=MAX_LOCK_WAITERS {
+        if matches!(receive(&mut stream).await.response, Response::Ok {}) {
+            granted += 1;
+        }
+    }
+    // The waiters and the unlock
+    assert_eq!(granted, MAX_LOCK_WAITERS + 1);
+}
+
+#[tokio::test]
+async fn reads_beyond_the_size_of_the_encoding_are_refused() {
+    let (_dir, addr) = server().await;
+    let mut stream = connect(&addr, &[]).await;
+    let ino = ino(&mut stream).await;
+    let max = Encoding::Json.max_io_size();
+
+    let read = |size| Call::Read { info: INFO, ino, fh: None, offset: 0, size };
+    send(&mut stream, 2, read(max + 1)).await;
+    match receive(&mut stream).await.response {
+        Response::IOError { error } => assert_eq!(error.kind, ErrorKind::InvalidInput),
+        r => panic!("{:?}", r),
+    }
+    send(&mut stream, 3, read(max)).await;
+    match receive(&mut stream).await.response {
+        Response::Read { data, .. } => assert_eq!(data, b"content"),
+        r => panic!("{:?}", r),
+    }
+}
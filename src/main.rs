@@ -1,8 +1,9 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use dns_lookup::lookup_host;
 #[cfg(feature = "fuse_client")]
 use fuse;
 use rich::{quit_error, unpack_or_default, unwrap_or_err};
+use shfs_api::encoding::Encoding;
 use shfs_api::responses::Response;
 use shfs_client::{ServerConnection, VolumeConnection};
 #[cfg(feature = "fuse_client")]
@@ -55,7 +56,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                         .help("filesystem options")
                         .takes_value(true)
                         .value_delimiter(","),
-                ),
+                )
+                .arg(encoding_arg()),
         )
         .subcommand(
             App::new("list")
@@ -64,20 +66,24 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     Arg::with_name("host")
                         .required(true)
                         .help("Fileserver Address"),
-                ),
+                )
+                .arg(encoding_arg()),
         )
         .subcommand(
-            App::new("info").about("list server info").arg(
-                Arg::with_name("host")
-                    .required(true)
-                    .help("Fileserver Address"),
-            ),
+            App::new("info")
+                .about("list server info")
+                .arg(
+                    Arg::with_name("host")
+                        .required(true)
+                        .help("Fileserver Address"),
+                )
+                .arg(encoding_arg()),
         )
         .get_matches();
 
     match args.subcommand() {
         ("list", Some(cmd)) => {
-            list_volumes(cmd.value_of("host").unwrap());
+            list_volumes(cmd.value_of("host").unwrap(), encoding(cmd));
         }
         ("serve", Some(cmd)) => {
             let config_file = cmd.value_of("config").unwrap();
@@ -94,13 +100,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 options = options_values.unwrap().collect();
             }
             #[cfg(feature = "fuse_client")]
-                mount_fs(host, mountpoint, options);
+                mount_fs(host, mountpoint, options, encoding(cmd));
             #[cfg(not(feature = "fuse_client"))]
             mountUnavailable();
         }
         ("info", Some(cmd)) => {
             let host = cmd.value_of("host").unwrap();
-            server_info(host);
+            server_info(host, encoding(cmd));
         }
         _ => {
             println!("{}", args.usage());
@@ -111,9 +117,26 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     return Ok(());
 }
 
-fn list_volumes(host: &str) {
+/// Returns the argument selecting the wire [Encoding]
+fn encoding_arg<'a, 'b>() -> Arg<'a, 'b> {
+    return Arg::with_name("encoding")
+        .long("encoding")
+        .possible_values(&["cbor", "json"])
+        .default_value("cbor")
+        .help("wire encoding, json is useful for debugging");
+}
+
+/// Returns the [Encoding] selected on the command line
+fn encoding(cmd: &ArgMatches) -> Encoding {
+    return unpack_or_default(
+        Encoding::from_name(cmd.value_of("encoding").unwrap()),
+        Encoding::Cbor,
+    );
+}
+
+fn list_volumes(host: &str, encoding: Encoding) {
     let (host, _) = resolve_host(host);
-    let mut con = ServerConnection::new(&host.to_string());
+    let mut con = ServerConnection::with_encoding(&host.to_string(), encoding);
     let vols = unwrap_or_err(con.list_volumes(), "");
     if vols.is_empty() {
         print!("Volumes : None\n");
@@ -125,14 +148,19 @@ fn list_volumes(host: &str) {
     }
 }
 
-fn server_info(host: &str) {
+fn server_info(host: &str, encoding: Encoding) {
     let (host, _) = resolve_host(host);
-    let mut con = ServerConnection::new(&host.to_string());
+    let mut con = ServerConnection::with_encoding(&host.to_string(), encoding);
     let info = unwrap_or_err(con.server_info(), "");
     match info {
-        Response::ServerInfo { name, version } => {
+        Response::ServerInfo {
+            name,
+            version,
+            protocol,
+        } => {
             println!("ShareFS Server {}", version);
             println!("Name : {}", name);
+            println!("Protocol Version : {}", protocol);
         }
         _ => {}
     }
@@ -148,7 +176,7 @@ fn server_info(host: &str) {
 }
 
 async fn host_server(config: &String, port: u32) {
-    let mut fs = unwrap_or_err(
+    let fs = unwrap_or_err(
         FileServer::new(&config, port).await,
         "Could not instantiate server",
     );
@@ -161,7 +189,7 @@ fn mountUnavailable() {
 }
 
 #[cfg(feature = "fuse_client")]
-fn mount_fs(host: &str, mountpoint: &str, options: Vec<&str>) {
+fn mount_fs(host: &str, mountpoint: &str, options: Vec<&str>, encoding: Encoding) {
     // Resolving host and determining the volume
     let (addr, volume) = resolve_host(host);
     if volume.is_empty() {
@@ -171,12 +199,12 @@ fn mount_fs(host: &str, mountpoint: &str, options: Vec<&str>) {
     println!("Mounting {} on {:?}", volume, addr);
 
     // Handshake
-    let mut srv = ServerConnection::new(&addr.to_string());
+    let mut srv = ServerConnection::with_encoding(&addr.to_string(), encoding);
     let vol_id = unwrap_or_err(srv.lookup_volume(&volume), "");
 
     // Creating the Filesystem and Connection
-    let fsapi = VolumeConnection::new(&addr.to_string(), vol_id);
-    let fs = shfs_fuse_fs::Filesystem { api: fsapi };
+    let fsapi = VolumeConnection::with_encoding(&addr.to_string(), vol_id, encoding);
+    let fs = shfs_fuse_fs::Filesystem::new(fsapi);
 
     // Parsing Filesystem Options
     let mut fuse_options = vec![];